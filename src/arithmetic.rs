@@ -0,0 +1,419 @@
+use thiserror::Error;
+
+use crate::shell::Shell;
+use crate::variables::VariableError;
+
+// Variables whose values refer to other variables are evaluated recursively
+const MAX_RECURSION: usize = 1024;
+
+#[derive(Debug, Error)]
+pub enum ArithmeticError {
+    #[error("division by 0 (error token is \"{0}\")")]
+    DivisionByZero(String),
+    #[error("syntax error: operand expected (error token is \"{0}\")")]
+    OperandExpected(String),
+    #[error("syntax error in expression (error token is \"{0}\")")]
+    Syntax(String),
+    #[error("value too great for base (error token is \"{0}\")")]
+    InvalidNumber(String),
+    #[error("invalid arithmetic base (error token is \"{0}\")")]
+    InvalidBase(String),
+    #[error("exponent less than 0 (error token is \"{0}\")")]
+    NegativeExponent(String),
+    #[error("attempted assignment to non-variable (error token is \"{0}\")")]
+    NotAVariable(String),
+    #[error("expression recursion level exceeded (error token is \"{0}\")")]
+    Recursion(String),
+    #[error(transparent)]
+    Variable(#[from] VariableError),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+// Longest operators first so that `<<=` is not read as `<` `<=`
+const OPERATORS: [&str; 39] = [
+    "<<=", ">>=", "**", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "++", "--", "+=", "-=",
+    "*=", "/=", "%=", "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "&", "^", "|", "!", "~",
+    "?", ":", "=", "(", ")", ",",
+];
+
+const ASSIGNMENT_OPERATORS: [&str; 11] = [
+    "=", "*=", "/=", "%=", "+=", "-=", "<<=", ">>=", "&=", "^=", "|=",
+];
+
+/// Binary operators from lowest to highest precedence, above `&&`
+const BINARY_LEVELS: [&[&str]; 8] = [
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+/// Evaluate an arithmetic expression whose parameters and command
+/// substitutions have already been expanded
+pub fn evaluate(shell: &mut Shell, expression: &str) -> Result<i64, ArithmeticError> {
+    evaluate_nested(shell, expression, 0)
+}
+
+fn evaluate_nested(
+    shell: &mut Shell,
+    expression: &str,
+    depth: usize,
+) -> Result<i64, ArithmeticError> {
+    if depth > MAX_RECURSION {
+        return Err(ArithmeticError::Recursion(expression.to_string()));
+    }
+    let tokens = tokenize(expression)?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+    let mut evaluator = Evaluator {
+        shell,
+        tokens,
+        pos: 0,
+        depth,
+    };
+    let value = evaluator.comma(false)?;
+    match evaluator.tokens.get(evaluator.pos) {
+        None => Ok(value),
+        Some(_) => Err(ArithmeticError::Syntax(evaluator.remaining())),
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, ArithmeticError> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let ch = chars[i];
+        if ch.is_whitespace() {
+            i += 1;
+        } else if ch.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || "#@_".contains(chars[i]))
+            {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            tokens.push(Token::Number(parse_number(&text)?));
+        } else if ch.is_ascii_alphabetic() || ch == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Name(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..].iter().collect();
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(*op))
+                .ok_or_else(|| ArithmeticError::Syntax(rest.clone()))?;
+            tokens.push(Token::Op(op));
+            i += op.len();
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Parse a decimal, octal (`017`), hexadecimal (`0x1f`) or `base#digits` constant
+fn parse_number(text: &str) -> Result<i64, ArithmeticError> {
+    let invalid = || ArithmeticError::InvalidNumber(text.to_string());
+
+    let (base, digits) = if let Some((base, digits)) = text.split_once('#') {
+        let base = base
+            .parse::<u32>()
+            .ok()
+            .filter(|base| (2..=64).contains(base))
+            .ok_or_else(|| ArithmeticError::InvalidBase(text.to_string()))?;
+        (base, digits)
+    } else if let Some(digits) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        (16, digits)
+    } else if text.len() > 1 && text.starts_with('0') {
+        (8, &text[1..])
+    } else {
+        (10, text)
+    };
+
+    if digits.is_empty() {
+        return Err(invalid());
+    }
+    let mut value: i64 = 0;
+    for ch in digits.chars() {
+        // Digits beyond 9 are letters, then `@` and `_`; case only matters above base 36
+        let digit = match ch {
+            '0'..='9' => ch as u32 - '0' as u32,
+            'a'..='z' => ch as u32 - 'a' as u32 + 10,
+            'A'..='Z' if base <= 36 => ch as u32 - 'A' as u32 + 10,
+            'A'..='Z' => ch as u32 - 'A' as u32 + 36,
+            '@' => 62,
+            '_' => 63,
+            _ => return Err(invalid()),
+        };
+        if digit >= base {
+            return Err(invalid());
+        }
+        value = value.wrapping_mul(base as i64).wrapping_add(digit as i64);
+    }
+    Ok(value)
+}
+
+/// Apply a binary operator; `token` names the right operand in error messages
+fn apply_binary(op: &str, left: i64, right: i64, token: &str) -> Result<i64, ArithmeticError> {
+    let value = match op {
+        "*" => left.wrapping_mul(right),
+        "/" | "%" if right == 0 => return Err(ArithmeticError::DivisionByZero(token.to_string())),
+        "/" => left.wrapping_div(right),
+        "%" => left.wrapping_rem(right),
+        "+" => left.wrapping_add(right),
+        "-" => left.wrapping_sub(right),
+        "<<" => left.wrapping_shl(right as u32),
+        ">>" => left.wrapping_shr(right as u32),
+        "<" => (left < right) as i64,
+        "<=" => (left <= right) as i64,
+        ">" => (left > right) as i64,
+        ">=" => (left >= right) as i64,
+        "==" => (left == right) as i64,
+        "!=" => (left != right) as i64,
+        "&" => left & right,
+        "^" => left ^ right,
+        "|" => left | right,
+        "**" if right < 0 => return Err(ArithmeticError::NegativeExponent(token.to_string())),
+        "**" => left.wrapping_pow(right.min(u32::MAX as i64) as u32),
+        _ => return Err(ArithmeticError::Syntax(op.to_string())),
+    };
+    Ok(value)
+}
+
+/// A recursive-descent evaluator. Operands of `&&`, `||` and `?:` that are not
+/// needed are still parsed, but with `skip` set so they have no side effects.
+struct Evaluator<'a> {
+    shell: &'a mut Shell,
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Evaluator<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn eat_op(&mut self, op: &str) -> bool {
+        if self.peek_op() == Some(op) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// The unparsed rest of the expression, for error messages
+    fn remaining(&self) -> String {
+        let tokens: Vec<String> = self.tokens[self.pos..]
+            .iter()
+            .map(|token| match token {
+                Token::Number(number) => number.to_string(),
+                Token::Name(name) => name.clone(),
+                Token::Op(op) => op.to_string(),
+            })
+            .collect();
+        tokens.join(" ")
+    }
+
+    fn variable(&mut self, name: &str) -> Result<i64, ArithmeticError> {
+        let value = self.shell.vars.get(name).unwrap_or_default().to_string();
+        evaluate_nested(self.shell, &value, self.depth + 1)
+    }
+
+    fn assign(&mut self, name: &str, value: i64, skip: bool) -> Result<i64, ArithmeticError> {
+        if !skip {
+            self.shell.vars.set(name, &value.to_string())?;
+        }
+        Ok(value)
+    }
+
+    /// `expr , expr`
+    fn comma(&mut self, skip: bool) -> Result<i64, ArithmeticError> {
+        let mut value = self.assignment(skip)?;
+        while self.eat_op(",") {
+            value = self.assignment(skip)?;
+        }
+        Ok(value)
+    }
+
+    /// `name = expr` and the compound assignment operators, right-associative
+    fn assignment(&mut self, skip: bool) -> Result<i64, ArithmeticError> {
+        if let (Some(Token::Name(name)), Some(Token::Op(op))) =
+            (self.peek().cloned(), self.tokens.get(self.pos + 1).cloned())
+        {
+            if ASSIGNMENT_OPERATORS.contains(&op) {
+                self.pos += 2;
+                let right_token = self.remaining();
+                let right = self.assignment(skip)?;
+                let value = match op.strip_suffix('=').filter(|op| !op.is_empty()) {
+                    Some(op) if !skip => {
+                        let left = self.variable(&name)?;
+                        apply_binary(op, left, right, &right_token)?
+                    }
+                    _ => right,
+                };
+                return self.assign(&name, value, skip);
+            }
+        }
+        self.conditional(skip)
+    }
+
+    /// `cond ? expr : expr`
+    fn conditional(&mut self, skip: bool) -> Result<i64, ArithmeticError> {
+        let condition = self.logical_or(skip)?;
+        if !self.eat_op("?") {
+            return Ok(condition);
+        }
+        let if_true = self.comma(skip || condition == 0)?;
+        if !self.eat_op(":") {
+            return Err(ArithmeticError::Syntax(self.remaining()));
+        }
+        let if_false = self.assignment(skip || condition != 0)?;
+        Ok(if condition != 0 { if_true } else { if_false })
+    }
+
+    fn logical_or(&mut self, skip: bool) -> Result<i64, ArithmeticError> {
+        let mut value = self.logical_and(skip)?;
+        while self.eat_op("||") {
+            let right = self.logical_and(skip || value != 0)?;
+            value = (value != 0 || right != 0) as i64;
+        }
+        Ok(value)
+    }
+
+    fn logical_and(&mut self, skip: bool) -> Result<i64, ArithmeticError> {
+        let mut value = self.binary(0, skip)?;
+        while self.eat_op("&&") {
+            let right = self.binary(0, skip || value == 0)?;
+            value = (value != 0 && right != 0) as i64;
+        }
+        Ok(value)
+    }
+
+    /// Left-associative binary operators at `BINARY_LEVELS[level]` and above
+    fn binary(&mut self, level: usize, skip: bool) -> Result<i64, ArithmeticError> {
+        if level == BINARY_LEVELS.len() {
+            return self.power(skip);
+        }
+        let mut value = self.binary(level + 1, skip)?;
+        while let Some(op) = self
+            .peek_op()
+            .filter(|op| BINARY_LEVELS[level].contains(op))
+        {
+            self.pos += 1;
+            let right_token = self.remaining();
+            let right = self.binary(level + 1, skip)?;
+            if !skip {
+                value = apply_binary(op, value, right, &right_token)?;
+            }
+        }
+        Ok(value)
+    }
+
+    /// `base ** exponent`, right-associative
+    fn power(&mut self, skip: bool) -> Result<i64, ArithmeticError> {
+        let base = self.unary(skip)?;
+        if !self.eat_op("**") {
+            return Ok(base);
+        }
+        let right_token = self.remaining();
+        let exponent = self.power(skip)?;
+        if skip {
+            return Ok(0);
+        }
+        apply_binary("**", base, exponent, &right_token)
+    }
+
+    fn unary(&mut self, skip: bool) -> Result<i64, ArithmeticError> {
+        match self.peek_op() {
+            Some(op @ ("++" | "--")) => {
+                self.pos += 1;
+                let name = match self.peek() {
+                    Some(Token::Name(name)) => name.clone(),
+                    _ => return Err(ArithmeticError::NotAVariable(self.remaining())),
+                };
+                self.pos += 1;
+                let value = self.variable(&name)?;
+                let value = if op == "++" {
+                    value.wrapping_add(1)
+                } else {
+                    value.wrapping_sub(1)
+                };
+                self.assign(&name, value, skip)
+            }
+            Some(op @ ("-" | "+" | "!" | "~")) => {
+                self.pos += 1;
+                let value = self.unary(skip)?;
+                Ok(match op {
+                    "-" => value.wrapping_neg(),
+                    "+" => value,
+                    "!" => (value == 0) as i64,
+                    _ => !value,
+                })
+            }
+            _ => self.primary(skip),
+        }
+    }
+
+    fn primary(&mut self, skip: bool) -> Result<i64, ArithmeticError> {
+        let token = match self.peek().cloned() {
+            Some(token) => token,
+            None => return Err(ArithmeticError::OperandExpected(String::new())),
+        };
+        self.pos += 1;
+
+        match token {
+            Token::Number(number) => Ok(number),
+            Token::Name(name) => {
+                let value = self.variable(&name)?;
+                match self.peek_op() {
+                    Some(op @ ("++" | "--")) => {
+                        self.pos += 1;
+                        let new_value = if op == "++" {
+                            value.wrapping_add(1)
+                        } else {
+                            value.wrapping_sub(1)
+                        };
+                        self.assign(&name, new_value, skip)?;
+                        Ok(value)
+                    }
+                    _ => Ok(value),
+                }
+            }
+            Token::Op("(") => {
+                let value = self.comma(skip)?;
+                if !self.eat_op(")") {
+                    return Err(ArithmeticError::Syntax(self.remaining()));
+                }
+                Ok(value)
+            }
+            Token::Op(_) => {
+                self.pos -= 1;
+                Err(ArithmeticError::OperandExpected(self.remaining()))
+            }
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::lexer::RedirectOp;
+
+/// A word as written on the command line, before quote removal
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Word {
+    pub raw: String,
+}
+
+impl Word {
+    pub fn new(raw: &str) -> Self {
+        Word {
+            raw: raw.to_string(),
+        }
+    }
+}
+
+/// The body of a here-document, filled in by the lexer once it reaches the
+/// lines that follow the command
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct HereDoc {
+    /// A quoted delimiter (`<<'EOF'`) turns off expansion in the body
+    pub quoted: bool,
+    pub body: RefCell<String>,
+}
+
+/// A redirection such as `2>> errors.log`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirection {
+    /// Explicit file descriptor in front of the operator, if any
+    pub fd: Option<i32>,
+    pub op: RedirectOp,
+    /// The file name, descriptor, here-string or here-document delimiter
+    pub target: Word,
+    pub here_doc: Option<Rc<HereDoc>>,
+}
+
+/// A `NAME=value` word written in front of a command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assignment {
+    pub name: String,
+    pub value: Word,
+}
+
+/// A command name with its arguments, variable assignments and redirections
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SimpleCommand {
+    pub assignments: Vec<Assignment>,
+    pub words: Vec<Word>,
+    pub redirections: Vec<Redirection>,
+}
+
+/// How a `case` item ends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaseTerminator {
+    /// `;;` stops after this item
+    Break,
+    /// `;&` also runs the next item's commands
+    FallThrough,
+    /// `;;&` goes on testing the patterns of the following items
+    Continue,
+}
+
+/// `pattern | pattern) commands ;;`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaseItem {
+    pub patterns: Vec<Word>,
+    pub body: List,
+    pub terminator: CaseTerminator,
+}
+
+/// Commands that group or control other commands
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompoundCommand {
+    /// `(( expression ))`, true when the expression is non-zero
+    Arithmetic(Word),
+    /// `if list; then list; [elif list; then list;]... [else list;] fi`
+    If {
+        branches: Vec<(List, List)>,
+        else_branch: Option<List>,
+    },
+    /// `while list; do list; done`
+    While { condition: List, body: List },
+    /// `until list; do list; done`
+    Until { condition: List, body: List },
+    /// `for name [in words]; do list; done`, where no `in` means `"$@"`
+    For {
+        name: String,
+        words: Option<Vec<Word>>,
+        body: List,
+    },
+    /// `for (( init; condition; update )); do list; done`
+    ArithmeticFor {
+        init: Word,
+        condition: Word,
+        update: Word,
+        body: List,
+    },
+    /// `case word in items esac`
+    Case { word: Word, items: Vec<CaseItem> },
+    /// `{ list; }`, run in the current shell
+    BraceGroup(List),
+    /// `( list )`, run in a forked copy of the shell
+    Subshell(List),
+}
+
+/// One element of a pipeline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Simple(SimpleCommand),
+    /// A compound command with the redirections written after it
+    Compound(CompoundCommand, Vec<Redirection>),
+    /// `name() compound-command` or `function name compound-command`
+    FunctionDefinition {
+        name: String,
+        body: Rc<Command>,
+    },
+}
+
+/// Commands connected with `|`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pipeline {
+    pub commands: Vec<Command>,
+    /// `! pipeline`, whose status is inverted
+    pub negated: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AndOrOp {
+    And,
+    Or,
+}
+
+/// Pipelines joined with `&&` and `||`, evaluated left to right
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AndOrList {
+    pub first: Pipeline,
+    pub rest: Vec<(AndOrOp, Pipeline)>,
+    /// Terminated by `&`, so the shell does not wait for it
+    pub background: bool,
+}
+
+/// And-or lists separated by `;`, `&` or newlines
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct List {
+    pub items: Vec<AndOrList>,
+}
@@ -10,7 +10,8 @@ use std::env;
 use std::fs;
 use std::io::{self, Write};
 
-const BUILTINS: &[&str] = &["echo", "exit", "type", "pwd", "cd", "ls"];
+use crate::parser::parse;
+use crate::shell::BUILT_IN_COMMANDS;
 
 #[derive(Default)]
 pub struct ShellCompleter {
@@ -107,7 +108,7 @@ impl Completer for ShellCompleter {
         }
 
         // First, check for builtin matches
-        let builtin_matches: Vec<_> = BUILTINS
+        let builtin_matches: Vec<_> = BUILT_IN_COMMANDS
             .iter()
             .filter(|builtin| builtin.starts_with(prefix))
             .map(|builtin| Pair {
@@ -218,8 +219,13 @@ impl Completer for ShellCompleter {
 impl Helper for ShellCompleter {}
 
 impl Validator for ShellCompleter {
-    fn validate(&self, _ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
-        Ok(ValidationResult::Valid(None))
+    /// Keep reading lines while the input is unfinished, e.g. an open quote or
+    /// a here-document that has not reached its delimiter yet
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        match parse(ctx.input()) {
+            Err(e) if e.is_incomplete() => Ok(ValidationResult::Incomplete),
+            _ => Ok(ValidationResult::Valid(None)),
+        }
     }
 }
 
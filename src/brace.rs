@@ -0,0 +1,180 @@
+use crate::lexer::{find_closing_backquote, find_closing_brace};
+use crate::parser::find_command_substitution_end;
+
+/// Expand `{a,b}` alternatives and `{x..y[..step]}` sequences in a raw word,
+/// leaving quoted and escaped braces and those in `${...}` untouched
+pub fn expand_braces(raw: &str) -> Vec<String> {
+    let chars: Vec<char> = raw.chars().collect();
+
+    let mut start = 0;
+    while let Some(open) = find_unquoted(&chars, start, |ch| ch == '{') {
+        if let Some((close, alternatives)) = brace_alternatives(&chars, open) {
+            let prefix: String = chars[..open].iter().collect();
+            let suffix: String = chars[close + 1..].iter().collect();
+            return alternatives
+                .iter()
+                .flat_map(|alternative| {
+                    expand_braces(&format!("{}{}{}", prefix, alternative, suffix))
+                })
+                .collect();
+        }
+        start = open + 1;
+    }
+
+    vec![raw.to_string()]
+}
+
+/// Index just past the quoted text or expansion starting at `chars[i]`,
+/// or `None` if `chars[i]` is an ordinary character
+fn skip_quoted(chars: &[char], i: usize) -> Option<usize> {
+    match chars[i] {
+        '\\' => Some(i + 2),
+        '\'' => Some(
+            chars[i + 1..]
+                .iter()
+                .position(|&ch| ch == '\'')
+                .map_or(chars.len(), |offset| i + offset + 2),
+        ),
+        '"' => {
+            let mut j = i + 1;
+            while j < chars.len() && chars[j] != '"' {
+                j = match chars[j] {
+                    // Single quotes are ordinary characters inside double quotes
+                    '\'' => j + 1,
+                    _ => skip_quoted(chars, j).unwrap_or(j + 1),
+                };
+            }
+            Some(j + 1)
+        }
+        '`' => Some(find_closing_backquote(chars, i + 1).map_or(chars.len(), |end| end + 1)),
+        '$' => match chars.get(i + 1) {
+            Some('{') => Some(find_closing_brace(chars, i + 2).map_or(chars.len(), |end| end + 1)),
+            Some('(') => {
+                Some(find_command_substitution_end(chars, i + 2).map_or(chars.len(), |end| end + 1))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Find the first unquoted character at or after `start` satisfying `predicate`
+fn find_unquoted(chars: &[char], start: usize, predicate: impl Fn(char) -> bool) -> Option<usize> {
+    let mut i = start;
+    while i < chars.len() {
+        if let Some(next) = skip_quoted(chars, i) {
+            i = next;
+        } else if predicate(chars[i]) {
+            return Some(i);
+        } else {
+            i += 1;
+        }
+    }
+    None
+}
+
+/// The closing brace and the alternatives of a valid brace expression at
+/// `chars[open]`, either a comma list or a sequence
+fn brace_alternatives(chars: &[char], open: usize) -> Option<(usize, Vec<String>)> {
+    let mut depth = 0;
+    let mut commas = Vec::new();
+    let mut i = open;
+    let close = loop {
+        i = find_unquoted(chars, i, |ch| matches!(ch, '{' | '}' | ','))?;
+        match chars[i] {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    break i;
+                }
+            }
+            _ if depth == 1 => commas.push(i),
+            _ => {}
+        }
+        i += 1;
+    };
+
+    if commas.is_empty() {
+        let content: String = chars[open + 1..close].iter().collect();
+        return sequence(&content).map(|items| (close, items));
+    }
+
+    let mut bounds = vec![open];
+    bounds.extend(commas);
+    bounds.push(close);
+    let alternatives = bounds
+        .windows(2)
+        .map(|pair| chars[pair[0] + 1..pair[1]].iter().collect())
+        .collect();
+    Some((close, alternatives))
+}
+
+/// Expand `x..y` or `x..y..step` over integers or single letters
+fn sequence(content: &str) -> Option<Vec<String>> {
+    let parts: Vec<&str> = content.split("..").collect();
+    let (first, last, step) = match parts.as_slice() {
+        [first, last] => (*first, *last, 1),
+        [first, last, step] => (*first, *last, step.parse::<i64>().ok()?),
+        _ => return None,
+    };
+    let step = step.unsigned_abs().max(1) as usize;
+
+    if let (Ok(start), Ok(end)) = (first.parse::<i64>(), last.parse::<i64>()) {
+        // A leading zero on either end pads every number to the same width
+        let is_padded = |text: &str| {
+            let digits = text.trim_start_matches('-');
+            digits.len() > 1 && digits.starts_with('0')
+        };
+        let width = if is_padded(first) || is_padded(last) {
+            first.len().max(last.len())
+        } else {
+            0
+        };
+        let numbers: Vec<i64> = if start <= end {
+            (start..=end).step_by(step).collect()
+        } else {
+            (end..=start).rev().step_by(step).collect()
+        };
+        return Some(
+            numbers
+                .into_iter()
+                .map(|number| {
+                    if number < 0 {
+                        format!(
+                            "-{:0width$}",
+                            number.unsigned_abs(),
+                            width = width.saturating_sub(1)
+                        )
+                    } else {
+                        format!("{:0width$}", number, width = width)
+                    }
+                })
+                .collect(),
+        );
+    }
+
+    let single_letter = |text: &str| {
+        let mut chars = text.chars();
+        match (chars.next(), chars.next()) {
+            (Some(ch), None) if ch.is_ascii_alphabetic() => Some(ch as u8),
+            _ => None,
+        }
+    };
+    let (start, end) = (single_letter(first)?, single_letter(last)?);
+    let letters: Vec<u8> = if start <= end {
+        (start..=end).step_by(step).collect()
+    } else {
+        (end..=start).rev().step_by(step).collect()
+    };
+    // Ranges such as `{Z..a}` include punctuation, which must stay literal when the word is expanded
+    Some(
+        letters
+            .into_iter()
+            .map(|ch| match ch as char {
+                ch if ch.is_ascii_alphanumeric() => ch.to_string(),
+                ch => format!("\\{}", ch),
+            })
+            .collect(),
+    )
+}
@@ -0,0 +1,1246 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+use nix::sys::signal::Signal;
+
+use crate::format::format_function;
+use crate::hash::{find_in_path, is_executable, search_path, DEFAULT_PATH};
+use crate::options::{SetOptions, ShoptOptions};
+use crate::output::Output;
+use crate::parser::KEYWORDS;
+use crate::shell::{is_builtin, Jump, Shell};
+use crate::traps::Trap;
+use crate::utils::{command_path, run_external_command};
+use crate::variables::Variable;
+
+/// Quote a value so that it can be read back by the shell, as `set` prints it
+pub fn shell_quote(value: &str) -> String {
+    let is_safe = |ch: char| ch.is_ascii_alphanumeric() || "_-./:=@%+,".contains(ch);
+    if !value.is_empty() && value.chars().all(is_safe) {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+
+/// Double-quote a value the way `declare -p` does
+fn double_quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for ch in value.chars() {
+        if matches!(ch, '"' | '\\' | '$' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(ch);
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn declare_line(name: &str, var: &Variable) -> String {
+    let mut flags = String::new();
+    if var.readonly {
+        flags.push('r');
+    }
+    if var.exported {
+        flags.push('x');
+    }
+    if flags.is_empty() {
+        flags.push('-');
+    }
+    format!("declare -{} {}={}", flags, name, double_quote(&var.value))
+}
+
+/// `export [-n] [-p] [name[=value] ...]`
+pub fn export(shell: &mut Shell, args: &[String], out: &mut Output) -> i32 {
+    let mut unexport = false;
+    let mut names = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "-n" => unexport = true,
+            "-p" => {}
+            _ if arg.starts_with('-') => {
+                eprintln!("export: {}: invalid option", arg);
+                eprintln!("export: usage: export [-n] [-p] [name[=value] ...]");
+                return 2;
+            }
+            _ => names.push(arg),
+        }
+    }
+
+    if names.is_empty() {
+        for (name, var) in shell.vars.iter() {
+            if var.exported {
+                writeln!(out, "{}", declare_line(name, var));
+            }
+        }
+        return 0;
+    }
+
+    let mut exit_code = 0;
+    for arg in names {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        let result = if unexport {
+            match value {
+                Some(value) => shell.vars.set(name, value),
+                None => Ok(()),
+            }
+            .map(|_| shell.vars.unexport(name))
+        } else {
+            shell.vars.export(name, value)
+        };
+        if let Err(e) = result {
+            eprintln!("export: {}", e);
+            exit_code = 1;
+        }
+    }
+    exit_code
+}
+
+/// `readonly [-p] [name[=value] ...]`
+pub fn readonly(shell: &mut Shell, args: &[String], out: &mut Output) -> i32 {
+    let names: Vec<&String> = args.iter().filter(|arg| arg.as_str() != "-p").collect();
+
+    if names.is_empty() {
+        for (name, var) in shell.vars.iter() {
+            if var.readonly {
+                writeln!(out, "{}", declare_line(name, var));
+            }
+        }
+        return 0;
+    }
+
+    let mut exit_code = 0;
+    for arg in names {
+        let result = match arg.split_once('=') {
+            Some((name, value)) => shell.vars.set_readonly(name, Some(value)),
+            None => shell.vars.set_readonly(arg, None),
+        };
+        if let Err(e) = result {
+            eprintln!("readonly: {}", e);
+            exit_code = 1;
+        }
+    }
+    exit_code
+}
+
+/// `unset [-fv] name ...`, where a plain name is a variable or else a function
+pub fn unset(shell: &mut Shell, args: &[String]) -> i32 {
+    let mut functions_only = false;
+    let mut variables_only = false;
+    let mut exit_code = 0;
+    for name in args {
+        match name.as_str() {
+            "-f" => functions_only = true,
+            "-v" => variables_only = true,
+            _ if functions_only => {
+                shell.functions.remove(name);
+            }
+            _ if !variables_only
+                && shell.vars.get_variable(name).is_none()
+                && shell.functions.contains_key(name) =>
+            {
+                shell.functions.remove(name);
+            }
+            _ => {
+                if let Err(e) = shell.vars.unset(name) {
+                    eprintln!("unset: {}", e);
+                    exit_code = 1;
+                }
+            }
+        }
+    }
+    exit_code
+}
+
+/// `set [-efnuvxC] [-o name] [+o name] [--] [args ...]` changes options and sets
+/// the positional parameters; without arguments it lists every shell variable
+pub fn set(shell: &mut Shell, args: &[String], out: &mut Output) -> i32 {
+    if args.is_empty() {
+        for (name, var) in shell.vars.iter() {
+            writeln!(out, "{}={}", name, shell_quote(&var.value));
+        }
+        return 0;
+    }
+
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        let (value, letters) = match arg.split_at(arg.len().min(1)) {
+            ("-", letters) => (true, letters),
+            ("+", letters) => (false, letters),
+            _ => {
+                shell.positional = args[i..].to_vec();
+                return 0;
+            }
+        };
+        match letters {
+            // `set -- args` replaces the positional parameters, even with none
+            "-" => {
+                shell.positional = args[i + 1..].to_vec();
+                return 0;
+            }
+            // A lone `-` turns off `-x` and `-v` and ends the options
+            "" => {
+                shell.options.xtrace = false;
+                shell.options.verbose = false;
+                shell.positional = args[i + 1..].to_vec();
+                return 0;
+            }
+            "o" => match args.get(i + 1) {
+                Some(name) => {
+                    if !shell.options.set(name, value) {
+                        eprintln!("set: {}: invalid option name", name);
+                        return 2;
+                    }
+                    i += 1;
+                }
+                None => list_options(shell, value, out),
+            },
+            _ => {
+                for letter in letters.chars() {
+                    match SetOptions::letter_name(letter) {
+                        Some(name) => {
+                            shell.options.set(name, value);
+                        }
+                        None => {
+                            eprintln!("set: {}{}: invalid option", &arg[..1], letter);
+                            eprintln!("set: usage: set [-efnuvxC] [-o option-name] [--] [arg ...]");
+                            return 2;
+                        }
+                    }
+                }
+            }
+        }
+        i += 1;
+    }
+    0
+}
+
+/// `set -o` lists the options as a table, `set +o` as commands that restore them
+fn list_options(shell: &Shell, table: bool, out: &mut Output) {
+    for name in SetOptions::NAMES {
+        let enabled = shell.options.get(name).unwrap_or(false);
+        if table {
+            writeln!(out, "{:<15}\t{}", name, if enabled { "on" } else { "off" });
+        } else {
+            writeln!(out, "set {}o {}", if enabled { '-' } else { '+' }, name);
+        }
+    }
+}
+
+/// `shift [n]`: drop the first `n` positional parameters
+pub fn shift(shell: &mut Shell, args: &[String]) -> i32 {
+    let count = match args.first() {
+        None => 1,
+        Some(arg) => match arg.parse::<i64>() {
+            Ok(count) if count >= 0 => count as usize,
+            Ok(_) => {
+                eprintln!("shift: {}: shift count out of range", arg);
+                return 1;
+            }
+            Err(_) => {
+                eprintln!("shift: {}: numeric argument required", arg);
+                return 1;
+            }
+        },
+    };
+    if count > shell.positional.len() {
+        return 1;
+    }
+    shell.positional.drain(..count);
+    0
+}
+
+/// `shopt [-pqsu] [optname ...]`
+pub fn shopt(shell: &mut Shell, args: &[String], out: &mut Output) -> i32 {
+    let mut value = None;
+    let mut print = false;
+    let mut quiet = false;
+    let mut names = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "-s" => value = Some(true),
+            "-u" => value = Some(false),
+            "-p" => print = true,
+            "-q" => quiet = true,
+            _ if arg.starts_with('-') => {
+                eprintln!("shopt: {}: invalid option", arg);
+                eprintln!("shopt: usage: shopt [-pqsu] [optname ...]");
+                return 2;
+            }
+            _ => names.push(arg.as_str()),
+        }
+    }
+
+    for name in &names {
+        if shell.shopt.get(name).is_none() {
+            eprintln!("shopt: {}: invalid shell option name", name);
+            return 1;
+        }
+    }
+
+    if let Some(value) = value {
+        if !names.is_empty() {
+            for name in names {
+                shell.shopt.set(name, value);
+            }
+            return 0;
+        }
+    }
+
+    let listed: Vec<&str> = if names.is_empty() {
+        ShoptOptions::NAMES.to_vec()
+    } else {
+        names.clone()
+    };
+    let mut exit_code = 0;
+    for name in listed {
+        let enabled = shell.shopt.get(name).unwrap_or(false);
+        // `-s` or `-u` without names lists only the options in that state
+        if value.is_some_and(|value| value != enabled) {
+            continue;
+        }
+        if !enabled {
+            exit_code = 1;
+        }
+        if quiet {
+            continue;
+        }
+        if print {
+            writeln!(out, "shopt {} {}", if enabled { "-s" } else { "-u" }, name);
+        } else {
+            writeln!(out, "{:<15}\t{}", name, if enabled { "on" } else { "off" });
+        }
+    }
+    if names.is_empty() && !quiet {
+        0
+    } else {
+        exit_code
+    }
+}
+
+/// `break [n]` and `continue [n]`, which act on the `n`th enclosing loop
+pub fn loop_control(shell: &mut Shell, name: &str, args: &[String]) -> i32 {
+    let count = match args.first() {
+        None => 1,
+        Some(arg) => match arg.parse::<i64>() {
+            Ok(count) if count >= 1 => count as usize,
+            Ok(_) => {
+                eprintln!("{}: {}: loop count out of range", name, arg);
+                return 1;
+            }
+            Err(_) => {
+                eprintln!("{}: {}: numeric argument required", name, arg);
+                return 1;
+            }
+        },
+    };
+    if shell.loop_depth == 0 {
+        eprintln!(
+            "{}: only meaningful in a `for', `while', or `until' loop",
+            name
+        );
+        return 0;
+    }
+
+    let count = count.min(shell.loop_depth);
+    shell.pending_jump = Some(if name == "break" {
+        Jump::Break(count)
+    } else {
+        Jump::Continue(count)
+    });
+    0
+}
+
+/// `local [name[=value] ...]` declares variables visible only to the running
+/// function and the functions it calls
+pub fn local(shell: &mut Shell, args: &[String]) -> i32 {
+    if !shell.vars.in_function() {
+        eprintln!("local: can only be used in a function");
+        return 1;
+    }
+    let mut exit_code = 0;
+    for arg in args {
+        let result = match arg.split_once('=') {
+            Some((name, value)) => shell.vars.make_local(name, Some(value)),
+            None => shell.vars.make_local(arg, None),
+        };
+        if let Err(e) = result {
+            eprintln!("local: {}", e);
+            exit_code = 1;
+        }
+    }
+    exit_code
+}
+
+/// `return [n]` leaves the running function with status `n`
+pub fn function_return(shell: &mut Shell, args: &[String]) -> i32 {
+    if shell.function_depth == 0 {
+        eprintln!("return: can only `return' from a function or sourced script");
+        return 1;
+    }
+    let exit_code = match args.first() {
+        None => shell.last_status,
+        Some(arg) => match arg.parse::<i64>() {
+            Ok(code) => (code & 0xff) as i32,
+            Err(_) => {
+                eprintln!("return: {}: numeric argument required", arg);
+                2
+            }
+        },
+    };
+    shell.pending_jump = Some(Jump::Return(exit_code));
+    exit_code
+}
+
+/// `declare [-fFprx] [name[=value] ...]`
+pub fn declare(shell: &mut Shell, args: &[String], out: &mut Output) -> i32 {
+    let mut flags = String::new();
+    let mut names = Vec::new();
+    for arg in args {
+        match arg.strip_prefix('-') {
+            Some(arg_flags) if names.is_empty() && !arg_flags.is_empty() => {
+                if let Some(flag) = arg_flags.chars().find(|flag| !"fFprx".contains(*flag)) {
+                    eprintln!("declare: -{}: invalid option", flag);
+                    eprintln!("declare: usage: declare [-fFprx] [name[=value] ...]");
+                    return 2;
+                }
+                flags.push_str(arg_flags);
+            }
+            _ => names.push(arg.as_str()),
+        }
+    }
+
+    // `-f` shows function definitions and `-F` only their names
+    if flags.contains('f') || flags.contains('F') {
+        let mut functions: Vec<&String> = if names.is_empty() {
+            shell.functions.keys().collect()
+        } else {
+            shell
+                .functions
+                .keys()
+                .filter(|name| names.contains(&name.as_str()))
+                .collect()
+        };
+        functions.sort();
+        for name in &functions {
+            if flags.contains('F') {
+                writeln!(out, "declare -f {}", name);
+            } else {
+                writeln!(out, "{}", format_function(name, &shell.functions[*name]));
+            }
+        }
+        return if functions.len() < names.len() { 1 } else { 0 };
+    }
+
+    if names.is_empty() || flags.contains('p') {
+        let mut exit_code = 0;
+        if names.is_empty() {
+            for (name, var) in shell.vars.iter() {
+                writeln!(out, "{}", declare_line(name, var));
+            }
+        }
+        for name in names {
+            match shell.vars.get_variable(name) {
+                Some(var) => writeln!(out, "{}", declare_line(name, var)),
+                None => {
+                    eprintln!("declare: {}: not found", name);
+                    exit_code = 1;
+                }
+            }
+        }
+        return exit_code;
+    }
+
+    let mut exit_code = 0;
+    for arg in names {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg, None),
+        };
+        // Inside a function, `declare` makes variables local like `local` does
+        let mut result = if shell.vars.in_function() {
+            shell.vars.make_local(name, value)
+        } else {
+            match value {
+                Some(value) => shell.vars.set(name, value),
+                None => Ok(()),
+            }
+        };
+        if result.is_ok() && flags.contains('x') {
+            result = shell.vars.export(name, None);
+        }
+        if result.is_ok() && flags.contains('r') {
+            result = shell.vars.set_readonly(name, None);
+        }
+        if let Err(e) = result {
+            eprintln!("declare: {}", e);
+            exit_code = 1;
+        }
+    }
+    exit_code
+}
+
+/// `jobs [-lprs] [jobspec ...]`
+pub fn jobs(shell: &mut Shell, args: &[String], out: &mut Output) -> i32 {
+    let mut flags = String::new();
+    let mut specs = Vec::new();
+    for arg in args {
+        match arg.strip_prefix('-') {
+            Some(arg_flags) if specs.is_empty() && !arg_flags.is_empty() => {
+                if let Some(flag) = arg_flags.chars().find(|flag| !"lprs".contains(*flag)) {
+                    eprintln!("jobs: -{}: invalid option", flag);
+                    eprintln!("jobs: usage: jobs [-lprs] [jobspec ...]");
+                    return 2;
+                }
+                flags.push_str(arg_flags);
+            }
+            _ => specs.push(arg.as_str()),
+        }
+    }
+
+    shell.jobs.reap();
+    let mut exit_code = 0;
+    let ids = if specs.is_empty() {
+        shell.jobs.ids()
+    } else {
+        let mut ids = Vec::new();
+        for spec in specs {
+            match shell.jobs.find(spec) {
+                Ok(id) => ids.push(id),
+                Err(e) => {
+                    eprintln!("jobs: {}", e);
+                    exit_code = 1;
+                }
+            }
+        }
+        ids
+    };
+
+    for id in ids {
+        let job = match shell.jobs.get(id) {
+            Some(job) => job,
+            None => continue,
+        };
+        if (flags.contains('r') && (job.is_stopped() || job.is_done()))
+            || (flags.contains('s') && !job.is_stopped())
+        {
+            continue;
+        }
+        if flags.contains('p') {
+            writeln!(out, "{}", job.pgid);
+        } else {
+            writeln!(out, "{}", shell.jobs.format(id, flags.contains('l')));
+        }
+        // Finished jobs are reported once, then forgotten
+        if job.is_done() {
+            shell.jobs.remove(id);
+        }
+    }
+    exit_code
+}
+
+/// `fg [jobspec]` and `bg [jobspec ...]`: continue stopped jobs
+pub fn foreground_background(
+    shell: &mut Shell,
+    command_name: &str,
+    args: &[String],
+    out: &mut Output,
+) -> i32 {
+    if !shell.job_control {
+        eprintln!("{}: no job control", command_name);
+        return 1;
+    }
+    let foreground = command_name == "fg";
+    let specs: Vec<&str> = if args.is_empty() {
+        vec!["%+"]
+    } else if foreground {
+        vec![args[0].as_str()]
+    } else {
+        args.iter().map(String::as_str).collect()
+    };
+
+    let mut exit_code = 0;
+    for spec in specs {
+        shell.jobs.reap();
+        let id = match shell.jobs.find(spec) {
+            Ok(id) => id,
+            Err(e) => {
+                eprintln!("{}: {}", command_name, e);
+                exit_code = 1;
+                continue;
+            }
+        };
+        let job = shell.jobs.get(id).unwrap();
+        if job.is_done() {
+            eprintln!("{}: job has terminated", command_name);
+            shell.jobs.remove(id);
+            exit_code = 1;
+        } else if !foreground && !job.is_stopped() {
+            eprintln!("bg: job {} already in background", id);
+        } else {
+            exit_code = shell.continue_job(id, foreground, out);
+        }
+    }
+    exit_code
+}
+
+/// `disown [-ar] [jobspec ...]`: remove jobs from the job table
+pub fn disown(shell: &mut Shell, args: &[String]) -> i32 {
+    let mut all = false;
+    let mut running = false;
+    let mut specs = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "-a" => all = true,
+            "-r" => running = true,
+            "-ar" | "-ra" => {
+                all = true;
+                running = true;
+            }
+            _ if arg.starts_with('-') => {
+                eprintln!("disown: {}: invalid option", arg);
+                eprintln!("disown: usage: disown [-ar] [jobspec ...]");
+                return 2;
+            }
+            _ => specs.push(arg.as_str()),
+        }
+    }
+
+    let ids = if all || (running && specs.is_empty()) {
+        shell.jobs.ids()
+    } else {
+        let mut ids = Vec::new();
+        if specs.is_empty() {
+            specs.push("%+");
+        }
+        for spec in specs {
+            match shell.jobs.find(spec) {
+                Ok(id) => ids.push(id),
+                Err(e) => {
+                    eprintln!("disown: {}", e);
+                    return 1;
+                }
+            }
+        }
+        ids
+    };
+    for id in ids {
+        let is_running = shell
+            .jobs
+            .get(id)
+            .is_some_and(|job| !job.is_stopped() && !job.is_done());
+        if !running || is_running {
+            shell.jobs.remove(id);
+        }
+    }
+    0
+}
+
+/// `trap [-lp] [[action] condition ...]`
+pub fn trap(shell: &mut Shell, args: &[String], out: &mut Output) -> i32 {
+    let mut print = args.is_empty();
+    let mut args = args;
+    match args.first().map(String::as_str) {
+        Some("-l") => {
+            let names: Vec<String> = Signal::iterator()
+                .map(|signal| format!("{:2}) {}", signal as i32, signal.as_str()))
+                .collect();
+            for line in names.chunks(5) {
+                writeln!(out, "{}", line.join("\t"));
+            }
+            return 0;
+        }
+        Some("-p") => {
+            print = true;
+            args = &args[1..];
+        }
+        Some("--") => args = &args[1..],
+        Some(arg) if arg.starts_with('-') && arg.len() > 1 => {
+            eprintln!("trap: {}: invalid option", arg);
+            eprintln!("trap: usage: trap [-lp] [[action] condition ...]");
+            return 2;
+        }
+        _ => {}
+    }
+
+    // Without an action, or with `-` or a number first, the conditions are reset
+    let (action, specs) = match args.split_first() {
+        _ if print => (None, args),
+        Some((first, rest)) if first == "-" => (None, rest),
+        Some((first, _)) if args.len() == 1 || first.parse::<u32>().is_ok() => (None, args),
+        Some((first, rest)) => (Some(first.as_str()), rest),
+        None => (None, args),
+    };
+
+    let mut exit_code = 0;
+    let mut traps = Vec::new();
+    for spec in specs {
+        match Trap::parse(spec) {
+            Some(trap) => traps.push(trap),
+            None => {
+                eprintln!("trap: {}: invalid signal specification", spec);
+                exit_code = 1;
+            }
+        }
+    }
+
+    if print {
+        let lines: Vec<String> = if specs.is_empty() {
+            shell
+                .traps
+                .iter()
+                .filter_map(|(trap, _)| shell.traps.format(trap))
+                .collect()
+        } else {
+            traps
+                .iter()
+                .filter_map(|&trap| shell.traps.format(trap))
+                .collect()
+        };
+        for line in lines {
+            writeln!(out, "{}", line);
+        }
+        return exit_code;
+    }
+
+    for trap in traps {
+        match action {
+            Some(action) => {
+                if let Err(e) = shell.traps.set(trap, action) {
+                    eprintln!("trap: {}: {}", trap.name(), e.desc());
+                    exit_code = 1;
+                }
+            }
+            None => shell.traps.reset(trap, shell.interactive),
+        }
+    }
+    exit_code
+}
+
+/// `echo [-neE] [arg ...]`
+pub fn echo(args: &[String], out: &mut Output) -> i32 {
+    let mut newline = true;
+    let mut escapes = false;
+    let mut args = args;
+    // Only words made entirely of known flags are options; anything else is printed
+    while let Some(flags) = args.first().and_then(|arg| arg.strip_prefix('-')) {
+        if flags.is_empty() || !flags.chars().all(|flag| "neE".contains(flag)) {
+            break;
+        }
+        for flag in flags.chars() {
+            match flag {
+                'n' => newline = false,
+                'e' => escapes = true,
+                _ => escapes = false,
+            }
+        }
+        args = &args[1..];
+    }
+
+    let mut bytes = Vec::new();
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            bytes.push(b' ');
+        }
+        if !escapes {
+            bytes.extend_from_slice(arg.as_bytes());
+        } else if echo_escapes(arg, &mut bytes) {
+            // `\c` ends the output, newline included
+            out.write_bytes(&bytes);
+            return 0;
+        }
+    }
+    if newline {
+        bytes.push(b'\n');
+    }
+    out.write_bytes(&bytes);
+    0
+}
+
+/// Append `arg` with the escapes of `echo -e` replaced, returning `true` at a `\c`
+fn echo_escapes(arg: &str, bytes: &mut Vec<u8>) -> bool {
+    fn digits(chars: &mut Peekable<Chars>, radix: u32, max: usize) -> Option<u32> {
+        let mut value = None;
+        for _ in 0..max {
+            match chars.peek().and_then(|ch| ch.to_digit(radix)) {
+                Some(digit) => {
+                    value = Some(value.unwrap_or(0) * radix + digit);
+                    chars.next();
+                }
+                None => break,
+            }
+        }
+        value
+    }
+
+    let mut chars = arg.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            let mut buffer = [0u8; 4];
+            bytes.extend_from_slice(ch.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+        let escaped = match chars.next() {
+            Some(escaped) => escaped,
+            None => {
+                bytes.push(b'\\');
+                break;
+            }
+        };
+        match escaped {
+            'a' => bytes.push(0x07),
+            'b' => bytes.push(0x08),
+            'c' => return true,
+            'e' | 'E' => bytes.push(0x1b),
+            'f' => bytes.push(0x0c),
+            'n' => bytes.push(b'\n'),
+            'r' => bytes.push(b'\r'),
+            't' => bytes.push(b'\t'),
+            'v' => bytes.push(0x0b),
+            '\\' => bytes.push(b'\\'),
+            '0' => bytes.push(digits(&mut chars, 8, 3).unwrap_or(0) as u8),
+            'x' => match digits(&mut chars, 16, 2) {
+                Some(value) => bytes.push(value as u8),
+                None => bytes.extend_from_slice(b"\\x"),
+            },
+            other => {
+                bytes.push(b'\\');
+                let mut buffer = [0u8; 4];
+                bytes.extend_from_slice(other.encode_utf8(&mut buffer).as_bytes());
+            }
+        }
+    }
+    false
+}
+
+/// `read [-r] [-p prompt] [name ...]`: read a line from stdin and split it on
+/// `IFS` into the named variables, the last one taking the rest of the line
+pub fn read(shell: &mut Shell, args: &[String]) -> i32 {
+    let mut raw = false;
+    let mut prompt = None;
+    let mut names = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-r" => raw = true,
+            "-p" => match args.next() {
+                Some(text) => prompt = Some(text),
+                None => {
+                    eprintln!("read: -p: option requires an argument");
+                    return 2;
+                }
+            },
+            "--" => {
+                names.extend(args.by_ref());
+                break;
+            }
+            _ if arg.starts_with('-') && names.is_empty() => {
+                eprintln!("read: {}: invalid option", arg);
+                eprintln!("read: usage: read [-r] [-p prompt] [name ...]");
+                return 2;
+            }
+            _ => names.push(arg),
+        }
+    }
+    if let Some(prompt) = prompt {
+        eprint!("{}", prompt);
+    }
+
+    // Each character remembers whether a backslash protected it from splitting
+    let mut line: Vec<(char, bool)> = Vec::new();
+    let mut bytes = Vec::new();
+    let mut escaped = false;
+    let mut at_end = true;
+    let mut byte = [0u8];
+    loop {
+        match nix::unistd::read(0, &mut byte) {
+            Ok(1) => {}
+            Err(nix::errno::Errno::EINTR) => continue,
+            _ => break,
+        }
+        if escaped {
+            escaped = false;
+            // A backslash before the newline joins the next line to this one
+            if byte[0] != b'\n' {
+                line.extend(
+                    String::from_utf8_lossy(&[byte[0]])
+                        .chars()
+                        .map(|ch| (ch, true)),
+                );
+            }
+            continue;
+        }
+        match byte[0] {
+            b'\n' => {
+                at_end = false;
+                break;
+            }
+            b'\\' if !raw => escaped = true,
+            byte => {
+                bytes.push(byte);
+                if std::str::from_utf8(&bytes).is_ok() || bytes.len() >= 4 {
+                    line.extend(
+                        String::from_utf8_lossy(&bytes)
+                            .chars()
+                            .map(|ch| (ch, false)),
+                    );
+                    bytes.clear();
+                }
+            }
+        }
+    }
+    line.extend(
+        String::from_utf8_lossy(&bytes)
+            .chars()
+            .map(|ch| (ch, false)),
+    );
+
+    let ifs = shell.vars.get("IFS").unwrap_or(" \t\n").to_string();
+    let fields = if names.is_empty() {
+        vec![("REPLY", unmarked(&line))]
+    } else {
+        let is_ifs = |&(ch, escaped): &(char, bool)| !escaped && ifs.contains(ch);
+        let is_space = |item: &(char, bool)| is_ifs(item) && " \t\n".contains(item.0);
+        let mut rest = &line[..];
+        let skip_spaces = |rest: &mut &[(char, bool)]| {
+            while rest.first().is_some_and(is_space) {
+                *rest = &rest[1..];
+            }
+        };
+        skip_spaces(&mut rest);
+        let mut fields = Vec::new();
+        for (i, name) in names.iter().enumerate() {
+            if i == names.len() - 1 {
+                let mut end = rest.len();
+                while end > 0 && is_space(&rest[end - 1]) {
+                    end -= 1;
+                }
+                fields.push((name.as_str(), unmarked(&rest[..end])));
+                break;
+            }
+            let end = rest.iter().position(is_ifs).unwrap_or(rest.len());
+            fields.push((name.as_str(), unmarked(&rest[..end])));
+            rest = &rest[end..];
+            // One delimiter ends a field: either whitespace, or one other
+            // `IFS` character with the whitespace around it
+            skip_spaces(&mut rest);
+            if rest
+                .first()
+                .is_some_and(|item| is_ifs(item) && !is_space(item))
+            {
+                rest = &rest[1..];
+                skip_spaces(&mut rest);
+            }
+        }
+        fields
+    };
+
+    for (name, value) in fields {
+        if let Err(e) = shell.vars.set(name, &value) {
+            eprintln!("read: {}", e);
+            return 1;
+        }
+    }
+    if at_end {
+        1
+    } else {
+        0
+    }
+}
+
+/// The characters of a line read by `read`, without their escape marks
+fn unmarked(line: &[(char, bool)]) -> String {
+    line.iter().map(|&(ch, _)| ch).collect()
+}
+
+/// `hash [-lr] [-p path] [-dt] [name ...]`: show or change the remembered locations of commands
+pub fn hash(shell: &mut Shell, args: &[String], out: &mut Output) -> i32 {
+    let mut flags = String::new();
+    let mut path = None;
+    let mut names = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.strip_prefix('-') {
+            Some(arg_flags) if names.is_empty() && !arg_flags.is_empty() => {
+                if let Some(flag) = arg_flags.chars().find(|flag| !"dlprt".contains(*flag)) {
+                    eprintln!("hash: -{}: invalid option", flag);
+                    eprintln!("hash: usage: hash [-lr] [-p pathname] [-dt] [name ...]");
+                    return 2;
+                }
+                if arg_flags.contains('p') {
+                    match args.next() {
+                        Some(arg) => path = Some(arg.as_str()),
+                        None => {
+                            eprintln!("hash: -p: option requires an argument");
+                            return 2;
+                        }
+                    }
+                }
+                flags.push_str(arg_flags);
+            }
+            _ => names.push(arg.as_str()),
+        }
+    }
+
+    if flags.contains('r') {
+        shell.command_hash().clear();
+    }
+    if names.is_empty() {
+        if flags.contains('r') {
+            return 0;
+        }
+        let table = shell.command_hash();
+        if table.is_empty() {
+            eprintln!("hash: hash table empty");
+            return 0;
+        }
+        if !flags.contains('l') {
+            writeln!(out, "hits\tcommand");
+        }
+        for (name, entry) in table.iter() {
+            if flags.contains('l') {
+                writeln!(out, "builtin hash -p {} {}", entry.path, name);
+            } else {
+                writeln!(out, "{:4}\t{}", entry.hits, entry.path);
+            }
+        }
+        return 0;
+    }
+
+    let mut exit_code = 0;
+    for name in &names {
+        if let Some(path) = path {
+            shell.command_hash().insert(name, path, 0);
+            continue;
+        }
+        let found = if flags.contains('d') {
+            shell.command_hash().remove(name).is_some()
+        } else if flags.contains('t') {
+            match shell.command_hash().get(name) {
+                Some(entry) if names.len() > 1 => writeln!(out, "{}\t{}", name, entry.path),
+                Some(entry) => writeln!(out, "{}", entry.path),
+                None => {}
+            }
+            shell.command_hash().get(name).is_some()
+        } else {
+            // Builtins and functions are never looked up in `PATH`
+            name.contains('/')
+                || is_builtin(name)
+                || shell.functions.contains_key(*name)
+                || shell.hash_command(name).is_some()
+        };
+        if !found {
+            eprintln!("hash: {}: not found", name);
+            exit_code = 1;
+        }
+    }
+    exit_code
+}
+
+/// What a command name runs, in the order the shell looks for it
+enum CommandKind {
+    Keyword,
+    Function,
+    Builtin,
+    /// A file, either remembered in the hash table or found by searching `PATH`
+    File {
+        path: String,
+        hashed: bool,
+    },
+}
+
+/// How `type` and `command` look a name up
+#[derive(Default)]
+struct Lookup {
+    /// Every match instead of only the one that runs
+    all: bool,
+    no_functions: bool,
+    /// Only files, as for `type -P`
+    files_only: bool,
+    /// Search `DEFAULT_PATH` instead of `PATH`, as for `command -p`
+    default_path: bool,
+}
+
+impl Lookup {
+    fn find(&self, shell: &mut Shell, name: &str) -> Vec<CommandKind> {
+        let mut kinds = Vec::new();
+        if !self.files_only {
+            if KEYWORDS.contains(&name) {
+                kinds.push(CommandKind::Keyword);
+            }
+            if !self.no_functions && shell.functions.contains_key(name) {
+                kinds.push(CommandKind::Function);
+            }
+            if is_builtin(name) {
+                kinds.push(CommandKind::Builtin);
+            }
+        }
+        if !kinds.is_empty() && !self.all {
+            kinds.truncate(1);
+            return kinds;
+        }
+
+        if name.contains('/') {
+            if is_executable(name) {
+                let path = name.to_string();
+                kinds.push(CommandKind::File {
+                    path,
+                    hashed: false,
+                });
+            }
+            return kinds;
+        }
+        if !self.all && !self.default_path {
+            if let Some(entry) = shell.command_hash().get(name) {
+                let path = entry.path.clone();
+                kinds.push(CommandKind::File { path, hashed: true });
+                return kinds;
+            }
+        }
+        let path_var = match self.default_path {
+            true => Some(DEFAULT_PATH),
+            false => shell.vars.get("PATH"),
+        };
+        let paths = search_path(name, path_var).take(if self.all { usize::MAX } else { 1 });
+        kinds.extend(paths.map(|path| CommandKind::File {
+            path,
+            hashed: false,
+        }));
+        kinds
+    }
+}
+
+/// Describe what `name` runs the way `type` and `command -V` do
+fn describe_command(shell: &Shell, name: &str, kind: &CommandKind, out: &mut Output) {
+    match kind {
+        CommandKind::Keyword => writeln!(out, "{} is a shell keyword", name),
+        CommandKind::Function => {
+            writeln!(out, "{} is a function", name);
+            writeln!(out, "{}", format_function(name, &shell.functions[name]));
+        }
+        CommandKind::Builtin => writeln!(out, "{} is a shell builtin", name),
+        CommandKind::File { path, hashed: true } => writeln!(out, "{} is hashed ({})", name, path),
+        CommandKind::File { path, .. } => writeln!(out, "{} is {}", name, path),
+    }
+}
+
+/// `type [-afptP] name ...`: tell how each name would be interpreted as a command
+pub fn command_type(shell: &mut Shell, args: &[String], out: &mut Output) -> i32 {
+    let mut flags = String::new();
+    let mut names = Vec::new();
+    for arg in args {
+        match arg.strip_prefix('-') {
+            Some(arg_flags) if names.is_empty() && !arg_flags.is_empty() => {
+                if let Some(flag) = arg_flags.chars().find(|flag| !"afptP".contains(*flag)) {
+                    eprintln!("type: -{}: invalid option", flag);
+                    eprintln!("type: usage: type [-afptP] name [name ...]");
+                    return 2;
+                }
+                flags.push_str(arg_flags);
+            }
+            _ => names.push(arg.as_str()),
+        }
+    }
+
+    let lookup = Lookup {
+        all: flags.contains('a'),
+        no_functions: flags.contains('f'),
+        files_only: flags.contains('P'),
+        default_path: false,
+    };
+    let mut exit_code = 0;
+    for name in names {
+        let kinds = lookup.find(shell, name);
+        if kinds.is_empty() {
+            // Only the verbose form complains about names that are not commands
+            if !flags.contains(['t', 'p', 'P']) {
+                eprintln!("{}: not found", name);
+            }
+            exit_code = 1;
+        }
+        for kind in &kinds {
+            if flags.contains('t') {
+                let word = match kind {
+                    CommandKind::Keyword => "keyword",
+                    CommandKind::Function => "function",
+                    CommandKind::Builtin => "builtin",
+                    CommandKind::File { .. } => "file",
+                };
+                writeln!(out, "{}", word);
+            } else if flags.contains(['p', 'P']) {
+                if let CommandKind::File { path, .. } = kind {
+                    writeln!(out, "{}", path);
+                }
+            } else {
+                describe_command(shell, name, kind, out);
+            }
+        }
+    }
+    exit_code
+}
+
+/// `command [-pVv] name [arg ...]`: run a builtin or file without looking for
+/// a function named `name`, or describe what `name` is
+pub fn command(shell: &mut Shell, args: &[String], out: &mut Output) -> i32 {
+    let mut flags = String::new();
+    let mut args = args;
+    while let Some((arg, rest)) = args.split_first() {
+        let arg_flags = match arg.strip_prefix('-') {
+            Some("-") => {
+                args = rest;
+                break;
+            }
+            Some(arg_flags) if !arg_flags.is_empty() => arg_flags,
+            _ => break,
+        };
+        if let Some(flag) = arg_flags.chars().find(|flag| !"pVv".contains(*flag)) {
+            eprintln!("command: -{}: invalid option", flag);
+            eprintln!("command: usage: command [-pVv] command [arg ...]");
+            return 2;
+        }
+        flags.push_str(arg_flags);
+        args = rest;
+    }
+    let lookup = Lookup {
+        default_path: flags.contains('p'),
+        ..Lookup::default()
+    };
+
+    if flags.contains(['v', 'V']) {
+        let mut exit_code = 0;
+        for name in args {
+            match lookup.find(shell, name).first() {
+                Some(kind) if flags.contains('V') => describe_command(shell, name, kind, out),
+                Some(CommandKind::File { path, .. }) => writeln!(out, "{}", path),
+                Some(_) => writeln!(out, "{}", name),
+                None => {
+                    if flags.contains('V') {
+                        eprintln!("command: {}: not found", name);
+                    }
+                    exit_code = 1;
+                }
+            }
+        }
+        return exit_code;
+    }
+
+    let (name, args) = match args.split_first() {
+        Some(split) => split,
+        None => return 0,
+    };
+    if is_builtin(name) {
+        return shell.execute_builtin(name, args);
+    }
+    // Redirections were applied to the shell before `command` ran, but the
+    // assignments in front of it belong in the environment of the file
+    let assignments = shell.builtin_assignments.clone();
+    let path = match lookup.default_path && !name.contains('/') {
+        true => find_in_path(name, Some(DEFAULT_PATH)),
+        false => command_path(shell, name, &assignments),
+    };
+    run_external_command(shell, name, path, args, &[], &assignments)
+}
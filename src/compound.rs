@@ -0,0 +1,235 @@
+use std::io::{self, Write};
+
+use nix::unistd::{fork, ForkResult};
+
+use crate::ast::{CaseItem, CaseTerminator, CompoundCommand, List, Word};
+use crate::expansion::{expand_arithmetic, expand_pattern, expand_word, expand_words};
+use crate::glob::pattern_matches;
+use crate::jobs::Job;
+use crate::shell::{Jump, Shell};
+
+impl Shell {
+    /// Execute a compound command in the shell process, returning its exit code
+    pub fn execute_compound(&mut self, compound: &CompoundCommand) -> i32 {
+        match compound {
+            CompoundCommand::Arithmetic(expression) => match expand_arithmetic(self, expression) {
+                Ok(value) => (value == 0) as i32,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    1
+                }
+            },
+            CompoundCommand::If {
+                branches,
+                else_branch,
+            } => {
+                for (condition, body) in branches {
+                    if self.execute_condition(condition) == 0 {
+                        return self.execute_list(body);
+                    }
+                    if self.pending_jump.is_some() {
+                        return 0;
+                    }
+                }
+                match else_branch {
+                    Some(body) => self.execute_list(body),
+                    None => 0,
+                }
+            }
+            CompoundCommand::While { condition, body } => {
+                self.execute_while(condition, body, false)
+            }
+            CompoundCommand::Until { condition, body } => self.execute_while(condition, body, true),
+            CompoundCommand::For { name, words, body } => self.execute_for(name, words, body),
+            CompoundCommand::ArithmeticFor {
+                init,
+                condition,
+                update,
+                body,
+            } => self.execute_arithmetic_for(init, condition, update, body),
+            CompoundCommand::Case { word, items } => self.execute_case(word, items),
+            CompoundCommand::BraceGroup(body) => self.execute_list(body),
+            CompoundCommand::Subshell(body) => self.execute_subshell(body),
+        }
+    }
+
+    /// Execute `body` in a forked copy of the shell, so that nothing it changes
+    /// reaches this one, and wait for it like any other foreground command
+    fn execute_subshell(&mut self, body: &List) -> i32 {
+        let _ = io::stdout().flush();
+        match unsafe { fork() } {
+            Ok(ForkResult::Child) => {
+                self.enter_child(None, true);
+                let exit_code = self.execute_list(body);
+                self.exit_shell(exit_code);
+            }
+            Ok(ForkResult::Parent { child }) => {
+                self.set_child_process_group(child, child);
+                let job = Job::new(child, &[child], &self.current_command);
+                self.wait_for_foreground(job)
+            }
+            Err(e) => {
+                eprintln!("fork: {}", e.desc());
+                1
+            }
+        }
+    }
+
+    /// Execute the condition of `if`, `while` or `until`, where a failure is not an error
+    fn execute_condition(&mut self, condition: &List) -> i32 {
+        self.condition_depth += 1;
+        let exit_code = self.execute_list(condition);
+        self.condition_depth -= 1;
+        exit_code
+    }
+
+    /// Consume a pending `break` or `continue` after part of a loop ran,
+    /// returning whether the loop has to stop
+    fn loop_should_stop(&mut self) -> bool {
+        match self.pending_jump {
+            None => false,
+            Some(Jump::Return(_)) => true,
+            Some(Jump::Break(count)) => {
+                self.pending_jump = (count > 1).then(|| Jump::Break(count - 1));
+                true
+            }
+            Some(Jump::Continue(count)) => {
+                self.pending_jump = (count > 1).then(|| Jump::Continue(count - 1));
+                count > 1
+            }
+        }
+    }
+
+    fn execute_while(&mut self, condition: &List, body: &List, until: bool) -> i32 {
+        let mut exit_code = 0;
+        self.loop_depth += 1;
+        loop {
+            let condition_code = self.execute_condition(condition);
+            if self.loop_should_stop() || (condition_code == 0) == until {
+                break;
+            }
+            exit_code = self.execute_list(body);
+            if self.loop_should_stop() {
+                break;
+            }
+        }
+        self.loop_depth -= 1;
+        exit_code
+    }
+
+    fn execute_for(&mut self, name: &str, words: &Option<Vec<Word>>, body: &List) -> i32 {
+        let values = match words {
+            Some(words) => match expand_words(self, words) {
+                Ok(values) => values,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return 1;
+                }
+            },
+            // Without `in`, the loop runs over the positional parameters
+            None => self.positional.clone(),
+        };
+
+        let mut exit_code = 0;
+        self.loop_depth += 1;
+        for value in values {
+            if let Err(e) = self.vars.set(name, &value) {
+                eprintln!("{}", e);
+                exit_code = 1;
+                break;
+            }
+            exit_code = self.execute_list(body);
+            if self.loop_should_stop() {
+                break;
+            }
+        }
+        self.loop_depth -= 1;
+        exit_code
+    }
+
+    fn execute_arithmetic_for(
+        &mut self,
+        init: &Word,
+        condition: &Word,
+        update: &Word,
+        body: &List,
+    ) -> i32 {
+        if let Err(e) = expand_arithmetic(self, init) {
+            eprintln!("{}", e);
+            return 1;
+        }
+
+        let mut exit_code = 0;
+        self.loop_depth += 1;
+        loop {
+            // An empty condition is always true
+            if !condition.raw.trim().is_empty() {
+                match expand_arithmetic(self, condition) {
+                    Ok(0) => break,
+                    Ok(_) => {}
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        exit_code = 1;
+                        break;
+                    }
+                }
+            }
+            exit_code = self.execute_list(body);
+            if self.loop_should_stop() {
+                break;
+            }
+            if let Err(e) = expand_arithmetic(self, update) {
+                eprintln!("{}", e);
+                exit_code = 1;
+                break;
+            }
+        }
+        self.loop_depth -= 1;
+        exit_code
+    }
+
+    fn execute_case(&mut self, word: &Word, items: &[CaseItem]) -> i32 {
+        let value = match expand_word(self, word) {
+            Ok(value) => value,
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        };
+
+        let mut exit_code = 0;
+        let mut fall_through = false;
+        for item in items {
+            if !fall_through {
+                let mut matched = false;
+                for pattern in &item.patterns {
+                    match expand_pattern(self, pattern) {
+                        Ok(pattern) if pattern_matches(&pattern, &value) => {
+                            matched = true;
+                            break;
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            eprintln!("{}", e);
+                            return 1;
+                        }
+                    }
+                }
+                if !matched {
+                    continue;
+                }
+            }
+
+            exit_code = self.execute_list(&item.body);
+            if self.pending_jump.is_some() {
+                break;
+            }
+            match item.terminator {
+                CaseTerminator::Break => break,
+                CaseTerminator::FallThrough => fall_through = true,
+                CaseTerminator::Continue => fall_through = false,
+            }
+        }
+        exit_code
+    }
+}
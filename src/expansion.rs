@@ -0,0 +1,1048 @@
+use nix::unistd::{getpid, getuid, User};
+use thiserror::Error;
+
+use crate::arithmetic::{evaluate, ArithmeticError};
+use crate::ast::{Assignment, Word};
+use crate::brace::expand_braces;
+use crate::glob::{escape_pattern, glob, pattern_matches};
+use crate::lexer::{find_arithmetic_end, find_closing_backquote, find_closing_brace};
+use crate::parser::find_command_substitution_end;
+use crate::shell::Shell;
+use crate::utils::command_substitution;
+use crate::variables::{is_valid_name, VariableError};
+
+const DEFAULT_IFS: &str = " \t\n";
+
+#[derive(Debug, Error)]
+pub enum ExpansionError {
+    #[error("{0}: {1}")]
+    Parameter(String, String),
+    #[error("{0}: unbound variable")]
+    Unbound(String),
+    #[error("{0}: bad substitution")]
+    BadSubstitution(String),
+    #[error("{0}: {1}")]
+    Arithmetic(String, ArithmeticError),
+    #[error("no match: {0}")]
+    NoMatch(String),
+    #[error("command substitution: {}", .0.desc())]
+    CommandSubstitution(nix::errno::Errno),
+    #[error(transparent)]
+    Variable(#[from] VariableError),
+}
+
+/// A run of expanded text together with how it was quoted
+#[derive(Debug, Clone)]
+struct Piece {
+    text: String,
+    /// Quoted text is never split into fields or treated as a pattern
+    quoted: bool,
+    /// Results of unquoted expansions are subject to field splitting
+    splittable: bool,
+    /// Ends the field before it, as between the parameters of `"$@"`
+    separator: bool,
+}
+
+/// A field produced by expansion, remembering which characters were quoted
+#[derive(Debug, Clone, Default)]
+struct Field {
+    chars: Vec<(char, bool)>,
+}
+
+impl Field {
+    fn text(&self) -> String {
+        self.chars.iter().map(|(ch, _)| ch).collect()
+    }
+
+    /// The field as a pattern in which quoted characters match literally
+    fn pattern(&self) -> String {
+        self.chars
+            .iter()
+            .map(|&(ch, quoted)| {
+                if quoted {
+                    escape_pattern(&ch.to_string())
+                } else {
+                    ch.to_string()
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Context {
+    Unquoted,
+    DoubleQuoted,
+    /// A here-document body: like double quotes, but `"` is an ordinary character
+    HereDoc,
+}
+
+struct Expander<'a> {
+    shell: &'a mut Shell,
+    pieces: Vec<Piece>,
+    /// Tilde expansion also applies after `=` and `:`, as in `PATH=~/bin:~/.local/bin`
+    assignment: bool,
+    /// Number of expansions so far, so that `""` only makes a field when nothing
+    /// was expanded between the quotes and `"$@"` can expand to no field at all
+    expansions: usize,
+}
+
+/// Expand command words: parameters are substituted, unquoted results are
+/// split into fields on `IFS`, and quotes are removed
+pub fn expand_words(shell: &mut Shell, words: &[Word]) -> Result<Vec<String>, ExpansionError> {
+    let mut fields = Vec::new();
+    for raw in words.iter().flat_map(|word| expand_braces(&word.raw)) {
+        let mut expander = Expander::new(shell);
+        expander.assignment = looks_like_assignment(&raw);
+        expander.expand(&raw, Context::Unquoted)?;
+        let pieces = expander.pieces;
+        let ifs = shell.vars.get("IFS").unwrap_or(DEFAULT_IFS);
+        for field in split_fields(pieces, ifs) {
+            expand_pathname(shell, &field, &mut fields)?;
+        }
+    }
+    Ok(fields)
+}
+
+/// Replace a field containing unquoted pattern characters with the sorted
+/// paths it matches, keeping it unchanged if nothing matches
+fn expand_pathname(
+    shell: &Shell,
+    field: &Field,
+    fields: &mut Vec<String>,
+) -> Result<(), ExpansionError> {
+    let is_pattern = !shell.options.noglob
+        && field
+            .chars
+            .iter()
+            .any(|&(ch, quoted)| !quoted && matches!(ch, '*' | '?' | '['));
+    if !is_pattern {
+        fields.push(field.text());
+        return Ok(());
+    }
+
+    let pattern = field.pattern();
+    let matches = glob(&pattern, &shell.shopt);
+    if !matches.is_empty() {
+        fields.extend(matches);
+    } else if shell.shopt.failglob {
+        return Err(ExpansionError::NoMatch(field.text()));
+    } else if !shell.shopt.nullglob {
+        fields.push(field.text());
+    }
+    Ok(())
+}
+
+/// Expand a word into a single string without field splitting, as used for
+/// assignments and redirection targets
+pub fn expand_word(shell: &mut Shell, word: &Word) -> Result<String, ExpansionError> {
+    let mut expander = Expander::new(shell);
+    expander.expand(&word.raw, Context::Unquoted)?;
+    Ok(expander.text())
+}
+
+/// Expand the values of `NAME=value` assignments from left to right, setting
+/// each variable before the next value is expanded
+pub fn expand_assignments(
+    shell: &mut Shell,
+    assignments: &[Assignment],
+) -> Result<Vec<(String, String)>, ExpansionError> {
+    assign_in_order(shell, assignments, true)
+}
+
+/// Set each assignment as soon as its value is expanded, tracing it first
+/// with `set -x` when it is not in front of a command
+fn assign_in_order(
+    shell: &mut Shell,
+    assignments: &[Assignment],
+    trace: bool,
+) -> Result<Vec<(String, String)>, ExpansionError> {
+    let mut expanded = Vec::new();
+    for assignment in assignments {
+        let mut expander = Expander::new(shell);
+        expander.assignment = true;
+        expander.expand(&assignment.value.raw, Context::Unquoted)?;
+        let assigned = (assignment.name.clone(), expander.text());
+        if trace {
+            shell.trace_command(std::slice::from_ref(&assigned), &[]);
+        }
+        shell.vars.set(&assigned.0, &assigned.1)?;
+        expanded.push(assigned);
+    }
+    Ok(expanded)
+}
+
+/// Expand the assignments in front of a command, each one seeing those before
+/// it, and put the variables back as they were for the command to apply them
+pub fn expand_prefix_assignments(
+    shell: &mut Shell,
+    assignments: &[Assignment],
+) -> Result<Vec<(String, String)>, ExpansionError> {
+    let previous: Vec<_> = assignments
+        .iter()
+        .map(|assignment| {
+            let name = assignment.name.clone();
+            (name, shell.vars.get_variable(&assignment.name).cloned())
+        })
+        .collect();
+    let expanded = assign_in_order(shell, assignments, false);
+    for (name, var) in previous.into_iter().rev() {
+        shell.vars.restore(&name, var);
+    }
+    expanded
+}
+
+fn home_directory(user: nix::Result<Option<User>>) -> Option<String> {
+    let user = user.ok()??;
+    Some(user.dir.to_string_lossy().into_owned())
+}
+
+/// Whether a command word has the form `name=value`
+fn looks_like_assignment(raw: &str) -> bool {
+    raw.split_once('=')
+        .is_some_and(|(name, _)| is_valid_name(name))
+}
+
+/// Expand a word into a pattern in which quoted characters match literally,
+/// as for `case` patterns
+pub fn expand_pattern(shell: &mut Shell, word: &Word) -> Result<String, ExpansionError> {
+    Expander::new(shell).expand_to_pattern(&word.raw)
+}
+
+/// Expand and evaluate the expression of a `(( ... ))` command
+pub fn expand_arithmetic(shell: &mut Shell, word: &Word) -> Result<i64, ExpansionError> {
+    Expander::new(shell).evaluate_number(&word.raw)
+}
+
+/// Expand the body of a here-document with an unquoted delimiter, where quotes
+/// are ordinary characters and backslash only escapes `$`, `` ` ``, `\` and newline
+pub fn expand_here_doc(shell: &mut Shell, body: &str) -> Result<String, ExpansionError> {
+    let mut expander = Expander::new(shell);
+    expander.expand(body, Context::HereDoc)?;
+    Ok(expander.text())
+}
+
+/// Split pieces into fields on the characters of `ifs`. Only unquoted
+/// expansion results are split; whitespace delimiters collapse while every
+/// other delimiter ends exactly one field.
+fn split_fields(pieces: Vec<Piece>, ifs: &str) -> Vec<Field> {
+    let mut fields = Vec::new();
+    let mut current = Field::default();
+    // Whether the current field exists even if empty, e.g. because of `""`
+    let mut started = false;
+    // Whether the last delimiter was whitespace, which absorbs a following non-whitespace one
+    let mut after_whitespace = false;
+
+    for piece in pieces {
+        if piece.separator {
+            // Quoted parameters are fields even when empty
+            if started || piece.quoted {
+                fields.push(std::mem::take(&mut current));
+            }
+            started = false;
+            after_whitespace = false;
+            continue;
+        }
+        if !piece.splittable {
+            if piece.quoted || !piece.text.is_empty() {
+                started = true;
+                after_whitespace = false;
+            }
+            current
+                .chars
+                .extend(piece.text.chars().map(|ch| (ch, piece.quoted)));
+            continue;
+        }
+
+        for ch in piece.text.chars() {
+            if !ifs.contains(ch) {
+                current.chars.push((ch, false));
+                started = true;
+                after_whitespace = false;
+            } else if ch.is_whitespace() {
+                if started {
+                    fields.push(std::mem::take(&mut current));
+                    started = false;
+                    after_whitespace = true;
+                }
+            } else {
+                if !after_whitespace || started {
+                    fields.push(std::mem::take(&mut current));
+                }
+                started = false;
+                after_whitespace = false;
+            }
+        }
+    }
+
+    if started {
+        fields.push(current);
+    }
+    fields
+}
+
+/// Remove the backslashes that quote `$`, `` ` `` and `\` inside backquotes,
+/// and those that quote `"` when the backquotes are in double quotes
+fn unescape_backquoted(chars: &[char], in_double_quotes: bool) -> String {
+    let mut command = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let quoted = match chars.get(i + 1) {
+            Some('$' | '`' | '\\') => true,
+            Some('"') => in_double_quotes,
+            _ => false,
+        };
+        if chars[i] == '\\' && quoted {
+            i += 1;
+        }
+        command.push(chars[i]);
+        i += 1;
+    }
+    command
+}
+
+/// Turn pieces into a pattern in which only unquoted characters are special
+fn pieces_to_pattern(pieces: &[Piece]) -> String {
+    pieces
+        .iter()
+        .map(|piece| {
+            if piece.quoted {
+                escape_pattern(&piece.text)
+            } else {
+                piece.text.clone()
+            }
+        })
+        .collect()
+}
+
+/// Length of the parameter name at the start of `text`: an identifier, a
+/// positional number or a single special character
+fn parameter_name_len(text: &str) -> usize {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(ch) if ch.is_ascii_alphabetic() || ch == '_' => {
+            1 + chars
+                .take_while(|ch| ch.is_ascii_alphanumeric() || *ch == '_')
+                .count()
+        }
+        Some(ch) if ch.is_ascii_digit() => 1 + chars.take_while(|ch| ch.is_ascii_digit()).count(),
+        Some('?' | '@' | '*' | '#' | '$' | '!' | '-') => 1,
+        _ => 0,
+    }
+}
+
+/// Split `name[index]` or `#name[index]` into the name, the index and whether
+/// the length is wanted
+fn split_subscript(content: &str) -> Option<(&str, &str, bool)> {
+    let (text, length) = match content.strip_prefix('#') {
+        Some(text) => (text, true),
+        None => (content, false),
+    };
+    let (name, index) = text.strip_suffix(']')?.split_once('[')?;
+    (!name.is_empty() && is_valid_name(name)).then_some((name, index, length))
+}
+
+/// Split `pattern/replacement` at the first unquoted, unescaped `/`
+fn split_replacement(spec: &str) -> (&str, Option<&str>) {
+    let mut in_single_quotes = false;
+    let mut in_double_quotes = false;
+    let mut escaped = false;
+    for (i, ch) in spec.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match ch {
+            '\\' if !in_single_quotes => escaped = true,
+            '\'' if !in_double_quotes => in_single_quotes = !in_single_quotes,
+            '"' if !in_single_quotes => in_double_quotes = !in_double_quotes,
+            '/' if !in_single_quotes && !in_double_quotes => {
+                return (&spec[..i], Some(&spec[i + 1..]));
+            }
+            _ => {}
+        }
+    }
+    (spec, None)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReplaceMode {
+    First,
+    All,
+    Prefix,
+    Suffix,
+}
+
+/// Replace matches of `pattern` in `value` for `${var/pattern/replacement}`
+fn replace_matches(value: &str, pattern: &str, replacement: &str, mode: ReplaceMode) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    let matches_range = |start: usize, end: usize| {
+        let text: String = chars[start..end].iter().collect();
+        pattern_matches(pattern, &text)
+    };
+
+    match mode {
+        ReplaceMode::Prefix => {
+            for end in (0..=chars.len()).rev() {
+                if matches_range(0, end) {
+                    let rest: String = chars[end..].iter().collect();
+                    return format!("{}{}", replacement, rest);
+                }
+            }
+            value.to_string()
+        }
+        ReplaceMode::Suffix => {
+            for start in 0..=chars.len() {
+                if matches_range(start, chars.len()) {
+                    let head: String = chars[..start].iter().collect();
+                    return format!("{}{}", head, replacement);
+                }
+            }
+            value.to_string()
+        }
+        ReplaceMode::First | ReplaceMode::All => {
+            let mut result = String::new();
+            let mut start = 0;
+            while start < chars.len() {
+                // Longest match starting here
+                let end = (start + 1..=chars.len())
+                    .rev()
+                    .find(|&end| matches_range(start, end));
+                match end {
+                    Some(end) => {
+                        result.push_str(replacement);
+                        start = end;
+                        if mode == ReplaceMode::First {
+                            break;
+                        }
+                    }
+                    None => {
+                        result.push(chars[start]);
+                        start += 1;
+                    }
+                }
+            }
+            result.extend(&chars[start..]);
+            result
+        }
+    }
+}
+
+/// Remove the shortest or longest prefix (or suffix) matching `pattern`
+fn remove_match(value: &str, pattern: &str, suffix: bool, longest: bool) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    let len = chars.len();
+    let text = |start: usize, end: usize| chars[start..end].iter().collect::<String>();
+
+    let mut cuts: Vec<usize> = (0..=len).collect();
+    // Prefixes are tried shortest first and suffixes from the end of the value
+    if suffix != longest {
+        cuts.reverse();
+    }
+    for cut in cuts {
+        if suffix && pattern_matches(pattern, &text(cut, len)) {
+            return text(0, cut);
+        }
+        if !suffix && pattern_matches(pattern, &text(0, cut)) {
+            return text(cut, len);
+        }
+    }
+    value.to_string()
+}
+
+impl<'a> Expander<'a> {
+    fn new(shell: &'a mut Shell) -> Self {
+        Expander {
+            shell,
+            pieces: Vec::new(),
+            assignment: false,
+            expansions: 0,
+        }
+    }
+
+    fn text(&self) -> String {
+        self.pieces
+            .iter()
+            .map(|piece| piece.text.as_str())
+            .collect()
+    }
+
+    fn push(&mut self, text: &str, quoted: bool, splittable: bool) {
+        if let Some(last) = self.pieces.last_mut() {
+            if last.quoted == quoted && last.splittable == splittable && !last.separator {
+                last.text.push_str(text);
+                return;
+            }
+        }
+        self.pieces.push(Piece {
+            text: text.to_string(),
+            quoted,
+            splittable,
+            separator: false,
+        });
+    }
+
+    /// Push the result of an expansion, which is split later unless quoted
+    fn push_value(&mut self, value: &str, quoted: bool) {
+        self.expansions += 1;
+        self.push(value, quoted, !quoted);
+    }
+
+    /// Push `$@` or `$*`, whose parameters become separate fields except in
+    /// `"$*"`, which joins them with the first character of `IFS`
+    fn push_positional(&mut self, name: &str, quoted: bool) {
+        if name == "*" && quoted {
+            let value = self.parameter_value(name).unwrap_or_default();
+            self.push_value(&value, quoted);
+            return;
+        }
+        let positional = self.shell.positional.clone();
+        self.push_fields(&positional, quoted);
+    }
+
+    /// Push values that become separate fields, as in `"$@"` and `"${name[@]}"`
+    fn push_fields(&mut self, values: &[String], quoted: bool) {
+        self.expansions += 1;
+        for (i, value) in values.iter().enumerate() {
+            if i > 0 {
+                // Joined with spaces wherever no fields are made, as in `a="$@"`
+                self.pieces.push(Piece {
+                    text: " ".to_string(),
+                    quoted,
+                    splittable: false,
+                    separator: true,
+                });
+            }
+            self.push_value(value, quoted);
+        }
+    }
+
+    /// Expand `raw` into a separate list of pieces, using the same shell
+    fn expand_nested(&mut self, raw: &str, context: Context) -> Result<Vec<Piece>, ExpansionError> {
+        let mut nested = Expander::new(self.shell);
+        nested.expand(raw, context)?;
+        Ok(nested.pieces)
+    }
+
+    /// Expand the word of `${x-word}` or `${x+word}` in place of the parameter.
+    /// Unquoted, its unquoted text is split into fields like any expansion.
+    fn expand_operand(&mut self, word: &str, context: Context) -> Result<(), ExpansionError> {
+        let mut pieces = self.expand_nested(word, context)?;
+        if context == Context::Unquoted {
+            for piece in pieces.iter_mut().filter(|piece| !piece.quoted) {
+                piece.splittable = true;
+            }
+        }
+        self.pieces.extend(pieces);
+        Ok(())
+    }
+
+    fn expand_to_string(&mut self, raw: &str, context: Context) -> Result<String, ExpansionError> {
+        let pieces = self.expand_nested(raw, context)?;
+        Ok(pieces.iter().map(|piece| piece.text.as_str()).collect())
+    }
+
+    fn expand_to_pattern(&mut self, raw: &str) -> Result<String, ExpansionError> {
+        let pieces = self.expand_nested(raw, Context::Unquoted)?;
+        Ok(pieces_to_pattern(&pieces))
+    }
+
+    fn expand(&mut self, raw: &str, context: Context) -> Result<(), ExpansionError> {
+        let chars: Vec<char> = raw.chars().collect();
+        let mut in_double_quotes = context != Context::Unquoted;
+        let mut opening_expansions = 0;
+        let mut i = 0;
+
+        while i < chars.len() {
+            let ch = chars[i];
+            match ch {
+                '\\' => match chars.get(i + 1) {
+                    Some(&next_ch) if !in_double_quotes => {
+                        self.push(&next_ch.to_string(), true, false);
+                        i += 2;
+                    }
+                    Some(&next_ch)
+                        if matches!(next_ch, '$' | '`' | '\\' | '\n')
+                            || (next_ch == '"' && context != Context::HereDoc) =>
+                    {
+                        if next_ch != '\n' {
+                            self.push(&next_ch.to_string(), true, false);
+                        }
+                        i += 2;
+                    }
+                    _ => {
+                        self.push("\\", true, false);
+                        i += 1;
+                    }
+                },
+                '\'' if !in_double_quotes => {
+                    let end = chars[i + 1..]
+                        .iter()
+                        .position(|&ch| ch == '\'')
+                        .map_or(chars.len(), |offset| i + 1 + offset);
+                    let text: String = chars[i + 1..end].iter().collect();
+                    self.push(&text, true, false);
+                    i = end + 1;
+                }
+                '"' if context != Context::HereDoc => {
+                    in_double_quotes = !in_double_quotes;
+                    if in_double_quotes {
+                        opening_expansions = self.expansions;
+                    } else if self.expansions == opening_expansions {
+                        // Even an empty pair of quotes produces a field
+                        self.push("", true, false);
+                    }
+                    i += 1;
+                }
+                '`' => {
+                    let end = find_closing_backquote(&chars, i + 1).unwrap_or(chars.len());
+                    // A here-document is like double quotes, except that `"` is not special
+                    let command = unescape_backquoted(
+                        &chars[i + 1..end.min(chars.len())],
+                        in_double_quotes && context != Context::HereDoc,
+                    );
+                    self.substitute_command(&command, in_double_quotes)?;
+                    i = end + 1;
+                }
+                '~' if !in_double_quotes && self.is_tilde_start(&chars, i) => {
+                    i = self.expand_tilde(&chars, i);
+                }
+                '$' => {
+                    let context = match context {
+                        Context::Unquoted if in_double_quotes => Context::DoubleQuoted,
+                        context => context,
+                    };
+                    i = self.expand_dollar(&chars, i, context)?;
+                }
+                _ => {
+                    self.push(&ch.to_string(), in_double_quotes, false);
+                    i += 1;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Expand the `$` expression at `chars[start]`, returning the index after it
+    fn expand_dollar(
+        &mut self,
+        chars: &[char],
+        start: usize,
+        context: Context,
+    ) -> Result<usize, ExpansionError> {
+        let quoted = context != Context::Unquoted;
+        let rest: String = chars[start + 1..].iter().collect();
+
+        if rest.starts_with("((") {
+            if let Some(end) = find_arithmetic_end(chars, start + 3) {
+                let expression: String = chars[start + 3..end].iter().collect();
+                let value = self.expand_number(&expression)?;
+                self.push_value(&value.to_string(), quoted);
+                return Ok(end + 2);
+            }
+        }
+
+        if rest.starts_with('(') {
+            let end = find_command_substitution_end(chars, start + 2)
+                .map_err(|_| ExpansionError::BadSubstitution(rest.clone()))?;
+            let command: String = chars[start + 2..end].iter().collect();
+            self.substitute_command(&command, quoted)?;
+            return Ok(end + 1);
+        }
+
+        if rest.starts_with('{') {
+            let end = find_closing_brace(chars, start + 2)
+                .ok_or_else(|| ExpansionError::BadSubstitution(rest.clone()))?;
+            let content: String = chars[start + 2..end].iter().collect();
+            self.expand_braced(&content, context)?;
+            return Ok(end + 1);
+        }
+
+        let name_len = match rest.chars().next() {
+            // Only `$0`..`$9` without braces; `$10` is `$1` followed by `0`
+            Some(ch) if ch.is_ascii_digit() => 1,
+            _ => parameter_name_len(&rest),
+        };
+        if name_len == 0 {
+            self.push("$", quoted, false);
+            return Ok(start + 1);
+        }
+
+        let name: String = rest.chars().take(name_len).collect();
+        if name == "@" || name == "*" {
+            self.push_positional(&name, quoted);
+        } else {
+            let value = match self.parameter_value(&name) {
+                Some(value) => value,
+                None if self.shell.options.nounset => return Err(self.unbound(&name)),
+                None => String::new(),
+            };
+            self.push_value(&value, quoted);
+        }
+        Ok(start + 1 + name_len)
+    }
+
+    fn is_tilde_start(&self, chars: &[char], i: usize) -> bool {
+        i == 0 || (self.assignment && matches!(chars[i - 1], '=' | ':'))
+    }
+
+    /// Expand `~`, `~user`, `~+` or `~-` at `chars[start]`, returning the index after it.
+    /// The prefix is left alone if it contains quotes or names an unknown user.
+    fn expand_tilde(&mut self, chars: &[char], start: usize) -> usize {
+        let end = (start + 1..chars.len())
+            .find(|&i| chars[i] == '/' || (self.assignment && chars[i] == ':'))
+            .unwrap_or(chars.len());
+        let prefix: String = chars[start + 1..end].iter().collect();
+        let is_literal = !prefix
+            .chars()
+            .any(|ch| matches!(ch, '\'' | '"' | '\\' | '$' | '`'));
+
+        let directory = match prefix.as_str() {
+            _ if !is_literal => None,
+            "" => self
+                .shell
+                .vars
+                .get("HOME")
+                .map(str::to_string)
+                .or_else(|| home_directory(User::from_uid(getuid()))),
+            "+" => self.shell.vars.get("PWD").map(str::to_string),
+            "-" => self.shell.vars.get("OLDPWD").map(str::to_string),
+            user => home_directory(User::from_name(user)),
+        };
+        match directory {
+            Some(directory) => {
+                self.push(&directory, true, false);
+                end
+            }
+            None => {
+                self.push("~", false, false);
+                start + 1
+            }
+        }
+    }
+
+    /// Replace `$(command)` with the output of running it in a subshell
+    fn substitute_command(&mut self, command: &str, quoted: bool) -> Result<(), ExpansionError> {
+        let output = command_substitution(self.shell, command)
+            .map_err(ExpansionError::CommandSubstitution)?;
+        self.push_value(&output, quoted);
+        Ok(())
+    }
+
+    fn parameter_value(&self, name: &str) -> Option<String> {
+        if name == "0" {
+            return Some(self.shell.shell_name.clone());
+        }
+        if let Ok(index) = name.parse::<usize>() {
+            return index
+                .checked_sub(1)
+                .and_then(|index| self.shell.positional.get(index).cloned());
+        }
+        match name {
+            "?" => Some(self.shell.last_status.to_string()),
+            "#" => Some(self.shell.positional.len().to_string()),
+            "$" => Some(self.shell.shell_pid.to_string()),
+            "!" => self.shell.last_background_pid.map(|pid| pid.to_string()),
+            "-" => Some(self.shell.option_flags()),
+            "@" => Some(self.shell.positional.join(" ")),
+            "*" => Some(self.shell.positional.join(&self.join_separator())),
+            "PIPESTATUS" => self.shell.pipe_status.first().map(i32::to_string),
+            _ => self.shell.vars.get(name).map(str::to_string),
+        }
+    }
+
+    /// With `set -u`, expanding an unset parameter is an error that ends a
+    /// non-interactive shell
+    fn unbound(&mut self, name: &str) -> ExpansionError {
+        let code = self.parameter_error_status();
+        self.fatal(ExpansionError::Unbound(name.to_string()), code)
+    }
+
+    /// The status that `set -u` and `${var?}` errors exit with: 127 from the
+    /// shell itself, but 1 from a subshell, as bash does
+    fn parameter_error_status(&self) -> i32 {
+        if getpid().as_raw() == self.shell.shell_pid {
+            127
+        } else {
+            1
+        }
+    }
+
+    /// Report an error that ends a non-interactive shell with `code`, as
+    /// `${var?}` and failed arithmetic do; an interactive shell only fails the command
+    fn fatal(&mut self, error: ExpansionError, code: i32) -> ExpansionError {
+        if !self.shell.interactive {
+            eprintln!("{}", error);
+            self.shell.exit_shell(code);
+        }
+        error
+    }
+
+    /// What `"$*"` puts between parameters: the first character of `IFS`
+    fn join_separator(&self) -> String {
+        match self.shell.vars.get("IFS") {
+            Some(ifs) => ifs.chars().next().map(String::from).unwrap_or_default(),
+            None => " ".to_string(),
+        }
+    }
+
+    /// The elements of `name` used as an array: `PIPESTATUS` has one for each
+    /// command of the last pipeline, and any other variable only its value
+    fn array_values(&self, name: &str) -> Vec<String> {
+        match name {
+            "PIPESTATUS" => self.shell.pipe_status.iter().map(i32::to_string).collect(),
+            _ => self
+                .shell
+                .vars
+                .get(name)
+                .map(str::to_string)
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    /// Expand `${name[index]}`, `${name[@]}`, `${name[*]}` or, with `length`,
+    /// the number of elements or the length of one
+    fn expand_subscript(
+        &mut self,
+        name: &str,
+        index: &str,
+        length: bool,
+        quoted: bool,
+    ) -> Result<(), ExpansionError> {
+        let values = self.array_values(name);
+        match index {
+            "@" | "*" if length => self.push_value(&values.len().to_string(), quoted),
+            "*" if quoted => {
+                let value = values.join(&self.join_separator());
+                self.push_value(&value, quoted);
+            }
+            "@" | "*" => self.push_fields(&values, quoted),
+            _ => {
+                // Negative indexes count back from the end
+                let index = self.expand_number(index)?;
+                let index = if index < 0 {
+                    values.len() as i64 + index
+                } else {
+                    index
+                };
+                let value = usize::try_from(index)
+                    .ok()
+                    .and_then(|index| values.get(index))
+                    .cloned()
+                    .unwrap_or_default();
+                if length {
+                    self.push_value(&value.chars().count().to_string(), quoted);
+                } else {
+                    self.push_value(&value, quoted);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Expand the inside of `${...}`
+    fn expand_braced(&mut self, content: &str, context: Context) -> Result<(), ExpansionError> {
+        let quoted = context != Context::Unquoted;
+        let bad_substitution = || ExpansionError::BadSubstitution(format!("${{{}}}", content));
+
+        if let Some((name, index, length)) = split_subscript(content) {
+            return self.expand_subscript(name, index, length, quoted);
+        }
+
+        // `${#name}` is the length of the value
+        if let Some(name) = content.strip_prefix('#') {
+            if !name.is_empty() {
+                if parameter_name_len(name) != name.len() {
+                    return Err(bad_substitution());
+                }
+                let length = match name {
+                    "@" | "*" => self.shell.positional.len(),
+                    _ => match self.parameter_value(name) {
+                        Some(value) => value.chars().count(),
+                        None if self.shell.options.nounset => return Err(self.unbound(name)),
+                        None => 0,
+                    },
+                };
+                self.push_value(&length.to_string(), quoted);
+                return Ok(());
+            }
+        }
+
+        let name_len = parameter_name_len(content);
+        if name_len == 0 {
+            return Err(bad_substitution());
+        }
+        let (name, operation) = content.split_at(name_len);
+        let value = self.parameter_value(name);
+
+        // Only the operators that test whether it is set accept an unset parameter
+        let tests_unset = matches!(
+            operation.trim_start_matches(':').chars().next(),
+            Some('-' | '=' | '?' | '+')
+        );
+        let is_positional = name == "@" || name == "*";
+        if value.is_none() && !tests_unset && !is_positional && self.shell.options.nounset {
+            return Err(self.unbound(name));
+        }
+
+        if operation.is_empty() {
+            if name == "@" || name == "*" {
+                self.push_positional(name, quoted);
+            } else {
+                self.push_value(&value.unwrap_or_default(), quoted);
+            }
+            return Ok(());
+        }
+
+        let (colon, operation) = match operation.strip_prefix(':') {
+            Some(operation) => (true, operation),
+            None => (false, operation),
+        };
+        let mut op_chars = operation.chars();
+        let op = op_chars.next().ok_or_else(bad_substitution)?;
+        let word = op_chars.as_str();
+
+        // With a colon, an empty value counts as unset
+        let is_unset = match &value {
+            None => true,
+            Some(value) => colon && value.is_empty(),
+        };
+
+        match op {
+            '-' => {
+                if is_unset {
+                    self.expand_operand(word, context)?;
+                } else {
+                    self.push_value(&value.unwrap_or_default(), quoted);
+                }
+            }
+            '=' => {
+                if is_unset {
+                    if !is_valid_name(name) {
+                        return Err(ExpansionError::Parameter(
+                            format!("${}", name),
+                            "cannot assign in this way".to_string(),
+                        ));
+                    }
+                    let new_value = self.expand_to_string(word, context)?;
+                    self.shell
+                        .vars
+                        .set(name, &new_value)
+                        .map_err(|e| ExpansionError::Parameter(name.to_string(), e.to_string()))?;
+                    self.push_value(&new_value, quoted);
+                } else {
+                    self.push_value(&value.unwrap_or_default(), quoted);
+                }
+            }
+            '?' => {
+                if is_unset {
+                    let message = match self.expand_to_string(word, context)? {
+                        message if message.is_empty() && colon => {
+                            "parameter null or not set".to_string()
+                        }
+                        message if message.is_empty() => "parameter not set".to_string(),
+                        message => message,
+                    };
+                    let error = ExpansionError::Parameter(name.to_string(), message);
+                    let code = self.parameter_error_status();
+                    return Err(self.fatal(error, code));
+                }
+                self.push_value(&value.unwrap_or_default(), quoted);
+            }
+            '+' => {
+                if !is_unset {
+                    self.expand_operand(word, context)?;
+                }
+            }
+            _ if colon => {
+                let value = value.unwrap_or_default();
+                let substring = self.substring(&value, operation)?;
+                self.push_value(&substring, quoted);
+            }
+            '#' | '%' => {
+                let longest = word.starts_with(op);
+                let pattern = if longest { &word[1..] } else { word };
+                let pattern = self.expand_to_pattern(pattern)?;
+                let value = value.unwrap_or_default();
+                let result = remove_match(&value, &pattern, op == '%', longest);
+                self.push_value(&result, quoted);
+            }
+            '/' => {
+                let (mode, spec) = match word.chars().next() {
+                    Some('/') => (ReplaceMode::All, &word[1..]),
+                    Some('#') => (ReplaceMode::Prefix, &word[1..]),
+                    Some('%') => (ReplaceMode::Suffix, &word[1..]),
+                    _ => (ReplaceMode::First, word),
+                };
+                let (pattern, replacement) = split_replacement(spec);
+                let pattern = self.expand_to_pattern(pattern)?;
+                let replacement = match replacement {
+                    Some(replacement) => self.expand_to_string(replacement, Context::Unquoted)?,
+                    None => String::new(),
+                };
+                let value = value.unwrap_or_default();
+                let result = if pattern.is_empty() {
+                    value
+                } else {
+                    replace_matches(&value, &pattern, &replacement, mode)
+                };
+                self.push_value(&result, quoted);
+            }
+            _ => return Err(bad_substitution()),
+        }
+
+        Ok(())
+    }
+
+    /// `${var:offset}` and `${var:offset:length}`, where negative numbers count from the end
+    fn substring(&mut self, value: &str, spec: &str) -> Result<String, ExpansionError> {
+        let (offset, length) = match spec.split_once(':') {
+            Some((offset, length)) => (offset, Some(length)),
+            None => (spec, None),
+        };
+        let offset = self.expand_number(offset)?;
+        let length = length
+            .map(|length| self.expand_number(length))
+            .transpose()?;
+
+        let chars: Vec<char> = value.chars().collect();
+        let len = chars.len() as i64;
+        let start = if offset < 0 { len + offset } else { offset };
+        if start < 0 || start > len {
+            return Ok(String::new());
+        }
+        let end = match length {
+            None => len,
+            Some(length) if length < 0 => {
+                let end = len + length;
+                if end < start {
+                    return Err(ExpansionError::Parameter(
+                        length.to_string(),
+                        "substring expression < 0".to_string(),
+                    ));
+                }
+                end
+            }
+            Some(length) => start.saturating_add(length).min(len),
+        };
+        Ok(chars[start as usize..end as usize].iter().collect())
+    }
+
+    /// Expand and evaluate an arithmetic expression, as in `$((...))` and substring
+    /// offsets, where an invalid one ends a non-interactive shell
+    fn expand_number(&mut self, raw: &str) -> Result<i64, ExpansionError> {
+        self.evaluate_number(raw).map_err(|e| match e {
+            ExpansionError::Arithmetic(..) => self.fatal(e, 1),
+            e => e,
+        })
+    }
+
+    /// Expand and evaluate an arithmetic expression, failing only the command if it is invalid
+    fn evaluate_number(&mut self, raw: &str) -> Result<i64, ExpansionError> {
+        let expression = self.expand_to_string(raw, Context::DoubleQuoted)?;
+        evaluate(self.shell, &expression)
+            .map_err(|e| ExpansionError::Arithmetic(expression.trim().to_string(), e))
+    }
+}
@@ -0,0 +1,257 @@
+use std::rc::Rc;
+
+use crate::ast::{
+    AndOrList, AndOrOp, CaseTerminator, Command, CompoundCommand, HereDoc, List, Pipeline,
+    Redirection, SimpleCommand,
+};
+use crate::parser::here_doc_delimiter;
+
+const INDENT: &str = "    ";
+
+/// Format a function definition the way `declare -f` and `type` show it
+pub fn format_function(name: &str, body: &Command) -> String {
+    let mut formatter = Formatter::default();
+    formatter.out.push_str(&format!("{} () \n", name));
+    formatter.command(body, 0);
+    formatter.out
+}
+
+/// Format an and-or list on one line, as `jobs` shows it
+pub fn format_and_or(and_or: &AndOrList) -> String {
+    let mut formatter = Formatter::default();
+    formatter.and_or(and_or, 0);
+    formatter.out
+}
+
+/// Format a pipeline on one line, as `jobs` shows it
+pub fn format_pipeline(pipeline: &Pipeline) -> String {
+    let mut formatter = Formatter::default();
+    formatter.pipeline(pipeline, 0);
+    formatter.out
+}
+
+/// Writes commands back out as shell source, one command per line
+#[derive(Default)]
+struct Formatter {
+    out: String,
+    /// Here-documents whose bodies follow the end of the current line
+    here_docs: Vec<(Rc<HereDoc>, String)>,
+}
+
+impl Formatter {
+    fn indent(&mut self, depth: usize) {
+        self.out.push_str(&INDENT.repeat(depth));
+    }
+
+    fn end_line(&mut self) {
+        self.out.push('\n');
+        for (here_doc, delimiter) in std::mem::take(&mut self.here_docs) {
+            self.out.push_str(&here_doc.body.borrow());
+            self.out.push_str(&delimiter);
+            self.out.push('\n');
+        }
+    }
+
+    /// Each and-or list on its own line at `depth`
+    fn list(&mut self, list: &List, depth: usize) {
+        for and_or in &list.items {
+            self.indent(depth);
+            self.and_or(and_or, depth);
+            if and_or.background {
+                self.out.push_str(" &");
+            }
+            self.end_line();
+        }
+    }
+
+    /// A list on a single line, as in the condition of `if` or `while`,
+    /// with each and-or list terminated by `;` or `&`
+    fn inline_list(&mut self, list: &List, depth: usize) {
+        for (i, and_or) in list.items.iter().enumerate() {
+            if i > 0 {
+                self.out.push(' ');
+            }
+            self.and_or(and_or, depth);
+            self.out
+                .push_str(if and_or.background { " &" } else { ";" });
+        }
+    }
+
+    fn and_or(&mut self, and_or: &AndOrList, depth: usize) {
+        self.pipeline(&and_or.first, depth);
+        for (op, pipeline) in &and_or.rest {
+            self.out.push_str(match op {
+                AndOrOp::And => " && ",
+                AndOrOp::Or => " || ",
+            });
+            self.pipeline(pipeline, depth);
+        }
+    }
+
+    fn pipeline(&mut self, pipeline: &Pipeline, depth: usize) {
+        if pipeline.negated {
+            self.out.push_str("! ");
+        }
+        for (i, command) in pipeline.commands.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(" | ");
+            }
+            self.command(command, depth);
+        }
+    }
+
+    fn command(&mut self, command: &Command, depth: usize) {
+        match command {
+            Command::Simple(simple) => self.simple_command(simple),
+            Command::Compound(compound, redirections) => {
+                self.compound(compound, depth);
+                self.redirections(redirections);
+            }
+            Command::FunctionDefinition { name, body } => {
+                self.out.push_str(&format!("{} () \n", name));
+                self.indent(depth);
+                self.command(body, depth);
+            }
+        }
+    }
+
+    fn simple_command(&mut self, command: &SimpleCommand) {
+        let words: Vec<String> = command
+            .assignments
+            .iter()
+            .map(|assignment| format!("{}={}", assignment.name, assignment.value.raw))
+            .chain(command.words.iter().map(|word| word.raw.clone()))
+            .collect();
+        self.out.push_str(&words.join(" "));
+        self.redirections(&command.redirections);
+    }
+
+    fn redirections(&mut self, redirections: &[Redirection]) {
+        for redirection in redirections {
+            self.out.push(' ');
+            if let Some(fd) = redirection.fd {
+                self.out.push_str(&fd.to_string());
+            }
+            self.out.push_str(redirection.op.as_str());
+            self.out.push_str(&redirection.target.raw);
+            if let Some(here_doc) = &redirection.here_doc {
+                let (delimiter, _) = here_doc_delimiter(&redirection.target.raw);
+                self.here_docs.push((Rc::clone(here_doc), delimiter));
+            }
+        }
+    }
+
+    /// `do` group of a loop, closing with `done` at `depth`
+    fn do_group(&mut self, body: &List, depth: usize) {
+        self.out.push_str(" do");
+        self.end_line();
+        self.list(body, depth + 1);
+        self.indent(depth);
+        self.out.push_str("done");
+    }
+
+    fn compound(&mut self, compound: &CompoundCommand, depth: usize) {
+        match compound {
+            CompoundCommand::Arithmetic(expression) => {
+                self.out.push_str(&format!("(({}))", expression.raw));
+            }
+            CompoundCommand::If {
+                branches,
+                else_branch,
+            } => {
+                for (i, (condition, body)) in branches.iter().enumerate() {
+                    if i > 0 {
+                        self.indent(depth);
+                        self.out.push_str("el");
+                    }
+                    self.out.push_str("if ");
+                    self.inline_list(condition, depth);
+                    self.out.push_str(" then");
+                    self.end_line();
+                    self.list(body, depth + 1);
+                }
+                if let Some(body) = else_branch {
+                    self.indent(depth);
+                    self.out.push_str("else");
+                    self.end_line();
+                    self.list(body, depth + 1);
+                }
+                self.indent(depth);
+                self.out.push_str("fi");
+            }
+            CompoundCommand::While { condition, body } => {
+                self.out.push_str("while ");
+                self.inline_list(condition, depth);
+                self.do_group(body, depth);
+            }
+            CompoundCommand::Until { condition, body } => {
+                self.out.push_str("until ");
+                self.inline_list(condition, depth);
+                self.do_group(body, depth);
+            }
+            CompoundCommand::For { name, words, body } => {
+                self.out.push_str(&format!("for {} in ", name));
+                match words {
+                    Some(words) => {
+                        let words: Vec<&str> = words.iter().map(|word| word.raw.as_str()).collect();
+                        self.out.push_str(&words.join(" "));
+                    }
+                    None => self.out.push_str("\"$@\""),
+                }
+                self.out.push(';');
+                self.do_group(body, depth);
+            }
+            CompoundCommand::ArithmeticFor {
+                init,
+                condition,
+                update,
+                body,
+            } => {
+                self.out.push_str(&format!(
+                    "for (({}; {}; {}))",
+                    init.raw.trim(),
+                    condition.raw.trim(),
+                    update.raw.trim()
+                ));
+                self.out.push(';');
+                self.do_group(body, depth);
+            }
+            CompoundCommand::Case { word, items } => {
+                self.out.push_str(&format!("case {} in ", word.raw));
+                self.end_line();
+                for item in items {
+                    let patterns: Vec<&str> =
+                        item.patterns.iter().map(|word| word.raw.as_str()).collect();
+                    self.indent(depth + 1);
+                    self.out.push_str(&format!("{})", patterns.join(" | ")));
+                    self.end_line();
+                    self.list(&item.body, depth + 2);
+                    self.indent(depth + 1);
+                    self.out.push_str(match item.terminator {
+                        CaseTerminator::Break => ";;",
+                        CaseTerminator::FallThrough => ";&",
+                        CaseTerminator::Continue => ";;&",
+                    });
+                    self.end_line();
+                }
+                self.indent(depth);
+                self.out.push_str("esac");
+            }
+            CompoundCommand::BraceGroup(body) => {
+                self.out.push_str("{ ");
+                self.end_line();
+                self.list(body, depth + 1);
+                self.indent(depth);
+                self.out.push('}');
+            }
+            CompoundCommand::Subshell(body) => {
+                self.out.push_str("( ");
+                self.inline_list(body, depth);
+                if self.out.ends_with(';') {
+                    self.out.pop();
+                }
+                self.out.push_str(" )");
+            }
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use std::fs;
+use std::path::Path;
+
+use crate::options::ShoptOptions;
+
+/// Check whether `text` matches a shell pattern with `*`, `?`, `[...]` and
+/// backslash escapes
+pub fn pattern_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    matches_chars(&pattern, &text)
+}
+
+/// Escape the characters that are special in patterns so `text` matches literally
+pub fn escape_pattern(text: &str) -> String {
+    let mut escaped = String::new();
+    for ch in text.chars() {
+        if matches!(ch, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
+
+fn matches_chars(pattern: &[char], text: &[char]) -> bool {
+    let mut p = 0;
+    let mut t = 0;
+    // Position of the most recent `*` and the text index it currently covers up to
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() {
+            match pattern[p] {
+                '*' => {
+                    backtrack = Some((p, t));
+                    p += 1;
+                    continue;
+                }
+                '?' => {
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+                '[' => {
+                    if let Some((matched, next)) = match_bracket(pattern, p, text[t]) {
+                        if matched {
+                            p = next;
+                            t += 1;
+                            continue;
+                        }
+                    } else if text[t] == '[' {
+                        // An unterminated bracket is an ordinary character
+                        p += 1;
+                        t += 1;
+                        continue;
+                    }
+                }
+                '\\' if p + 1 < pattern.len() => {
+                    if pattern[p + 1] == text[t] {
+                        p += 2;
+                        t += 1;
+                        continue;
+                    }
+                }
+                ch => {
+                    if ch == text[t] {
+                        p += 1;
+                        t += 1;
+                        continue;
+                    }
+                }
+            }
+        }
+
+        match backtrack {
+            Some((star, covered)) => {
+                p = star + 1;
+                t = covered + 1;
+                backtrack = Some((star, covered + 1));
+            }
+            None => return false,
+        }
+    }
+
+    while p < pattern.len() && pattern[p] == '*' {
+        p += 1;
+    }
+    p == pattern.len()
+}
+
+/// Match `ch` against the bracket expression starting at `pattern[start]`,
+/// returning whether it matched and the index just after the closing `]`.
+/// Returns `None` if the bracket is never closed.
+fn match_bracket(pattern: &[char], start: usize, ch: char) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negated = matches!(pattern.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let current = *pattern.get(i)?;
+        if current == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+
+        // Character classes such as `[:alpha:]`
+        if current == '[' && pattern.get(i + 1) == Some(&':') {
+            let rest: String = pattern[i + 2..].iter().collect();
+            if let Some(end) = rest.find(":]") {
+                let class = &rest[..end];
+                if char_class_matches(class, ch) {
+                    matched = true;
+                }
+                i += 2 + rest[..end].chars().count() + 2;
+                continue;
+            }
+        }
+
+        let (low, next) = bracket_char(pattern, i)?;
+        i = next;
+        if pattern.get(i) == Some(&'-') && pattern.get(i + 1).is_some_and(|&c| c != ']') {
+            let (high, next) = bracket_char(pattern, i + 1)?;
+            i = next;
+            if low <= ch && ch <= high {
+                matched = true;
+            }
+        } else if low == ch {
+            matched = true;
+        }
+    }
+}
+
+fn bracket_char(pattern: &[char], i: usize) -> Option<(char, usize)> {
+    match pattern.get(i)? {
+        '\\' => Some((*pattern.get(i + 1)?, i + 2)),
+        &ch => Some((ch, i + 1)),
+    }
+}
+
+fn char_class_matches(class: &str, ch: char) -> bool {
+    match class {
+        "alnum" => ch.is_alphanumeric(),
+        "alpha" => ch.is_alphabetic(),
+        "blank" => ch == ' ' || ch == '\t',
+        "cntrl" => ch.is_control(),
+        "digit" => ch.is_ascii_digit(),
+        "graph" => !ch.is_whitespace() && !ch.is_control(),
+        "lower" => ch.is_lowercase(),
+        "print" => !ch.is_control(),
+        "punct" => ch.is_ascii_punctuation(),
+        "space" => ch.is_whitespace(),
+        "upper" => ch.is_uppercase(),
+        "xdigit" => ch.is_ascii_hexdigit(),
+        _ => false,
+    }
+}
+
+/// Whether `pattern` contains an unescaped `*`, `?` or `[`
+pub fn has_glob_chars(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Remove the backslashes that escape characters in a pattern
+fn unescape_pattern(pattern: &str) -> String {
+    let mut text = String::new();
+    let mut chars = pattern.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => text.extend(chars.next()),
+            _ => text.push(ch),
+        }
+    }
+    text
+}
+
+/// Expand a pathname pattern into the sorted list of existing paths it matches
+pub fn glob(pattern: &str, options: &ShoptOptions) -> Vec<String> {
+    let (prefix, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => ("/", rest),
+        None => ("", pattern),
+    };
+    let components: Vec<&str> = rest.split('/').collect();
+    let mut matches = Vec::new();
+    glob_in(prefix, &components, options, &mut matches);
+    matches.sort();
+    matches.dedup();
+    matches
+}
+
+/// Names in the directory `prefix` (empty for the current directory), with
+/// whether each one is a directory, skipping hidden files unless `show_hidden`
+fn read_dir_names(prefix: &str, show_hidden: bool) -> Vec<(String, bool)> {
+    let dir = if prefix.is_empty() { "." } else { prefix };
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().into_string().ok()?;
+            if name.starts_with('.') && !show_hidden {
+                return None;
+            }
+            let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+            Some((name, is_dir))
+        })
+        .collect()
+}
+
+/// Match `components` against the directory `prefix`, which is empty or ends in `/`
+fn glob_in(prefix: &str, components: &[&str], options: &ShoptOptions, matches: &mut Vec<String>) {
+    let (component, rest) = match components.split_first() {
+        Some(split) => split,
+        None => {
+            matches.push(prefix.to_string());
+            return;
+        }
+    };
+
+    // A trailing `/` only matches directories; `//` is the same as `/`
+    if component.is_empty() {
+        if rest.is_empty() {
+            if !prefix.is_empty() && Path::new(prefix).is_dir() {
+                matches.push(prefix.to_string());
+            }
+        } else {
+            glob_in(prefix, rest, options, matches);
+        }
+        return;
+    }
+
+    if !has_glob_chars(component) {
+        let path = format!("{}{}", prefix, unescape_pattern(component));
+        if rest.is_empty() {
+            if fs::symlink_metadata(&path).is_ok() {
+                matches.push(path);
+            }
+        } else {
+            glob_in(&format!("{}/", path), rest, options, matches);
+        }
+        return;
+    }
+
+    // With globstar, `**` matches any number of directories, without following symlinks
+    if *component == "**" && options.globstar {
+        for (name, is_dir) in read_dir_names(prefix, options.dotglob) {
+            let path = format!("{}{}", prefix, name);
+            if rest.is_empty() {
+                matches.push(path.clone());
+            }
+            if is_dir {
+                glob_in(&format!("{}/", path), components, options, matches);
+            }
+        }
+        if !rest.is_empty() {
+            glob_in(prefix, rest, options, matches);
+        }
+        return;
+    }
+
+    // Hidden files only match a pattern that starts with a literal `.`
+    let show_hidden = options.dotglob || component.starts_with('.');
+    for (name, _) in read_dir_names(prefix, show_hidden) {
+        if pattern_matches(component, &name) {
+            let path = format!("{}{}", prefix, name);
+            if rest.is_empty() {
+                matches.push(path);
+            } else {
+                glob_in(&format!("{}/", path), rest, options, matches);
+            }
+        }
+    }
+}
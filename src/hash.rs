@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+use std::fs;
+
+use nix::unistd::{access, AccessFlags};
+
+use crate::shell::Shell;
+
+/// A command found in `PATH` and how many times it has been run since
+#[derive(Debug, Clone)]
+pub struct HashEntry {
+    pub path: String,
+    pub hits: usize,
+}
+
+/// Remembered locations of commands, so that `PATH` is searched once per command
+#[derive(Debug, Clone, Default)]
+pub struct CommandHash {
+    entries: BTreeMap<String, HashEntry>,
+    /// The `PATH` the entries were found with; a different one forgets them all
+    path_var: Option<String>,
+}
+
+impl CommandHash {
+    pub fn get(&self, name: &str) -> Option<&HashEntry> {
+        self.entries.get(name)
+    }
+
+    pub fn insert(&mut self, name: &str, path: &str, hits: usize) {
+        let entry = HashEntry {
+            path: path.to_string(),
+            hits,
+        };
+        self.entries.insert(name.to_string(), entry);
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<HashEntry> {
+        self.entries.remove(name)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &HashEntry)> {
+        self.entries.iter()
+    }
+
+    /// Forget every entry if `PATH` is not what it was when they were found
+    fn check_path_var(&mut self, path_var: Option<&str>) {
+        if self.path_var.as_deref() != path_var {
+            self.entries.clear();
+            self.path_var = path_var.map(str::to_string);
+        }
+    }
+}
+
+/// Whether `path` is a regular file, or a link to one, that may be executed
+pub fn is_executable(path: &str) -> bool {
+    fs::metadata(path).is_ok_and(|metadata| metadata.is_file())
+        && access(path, AccessFlags::X_OK).is_ok()
+}
+
+/// The `PATH` that `command -p` searches, which finds the standard utilities
+pub const DEFAULT_PATH: &str = "/usr/bin:/bin";
+
+/// Every executable `command` in the directories of `path_var`, in order.
+/// An empty entry stands for the current directory.
+pub fn search_path<'a>(
+    command: &'a str,
+    path_var: Option<&'a str>,
+) -> impl Iterator<Item = String> + 'a {
+    path_var
+        .into_iter()
+        .flat_map(|path_var| path_var.split(':'))
+        .map(move |directory| match directory {
+            "" => format!("./{}", command),
+            directory => format!("{}/{}", directory.trim_end_matches('/'), command),
+        })
+        .filter(|path| is_executable(path))
+}
+
+/// The first executable `command` in the directories of `path_var`
+pub fn find_in_path(command: &str, path_var: Option<&str>) -> Option<String> {
+    search_path(command, path_var).next()
+}
+
+impl Shell {
+    /// The hash table, emptied first if `PATH` changed since it was filled
+    pub fn command_hash(&mut self) -> &mut CommandHash {
+        let path_var = self.vars.get("PATH").map(str::to_string);
+        self.hash.check_path_var(path_var.as_deref());
+        &mut self.hash
+    }
+
+    /// The file a command name runs: the name itself if it contains a `/`,
+    /// otherwise the remembered or newly found location in `PATH`
+    pub fn find_command(&mut self, name: &str) -> Option<String> {
+        if name.contains('/') {
+            return Some(name.to_string());
+        }
+        if let Some(entry) = self.command_hash().entries.get_mut(name) {
+            // A command that was removed or moved is searched for again
+            if is_executable(&entry.path) {
+                entry.hits += 1;
+                return Some(entry.path.clone());
+            }
+        }
+        let path = find_in_path(name, self.vars.get("PATH"));
+        match &path {
+            Some(path) => self.hash.insert(name, path, 1),
+            None => {
+                self.hash.remove(name);
+            }
+        }
+        path
+    }
+
+    /// Look `name` up in `PATH` for `hash`, remembering it without counting a hit
+    pub fn hash_command(&mut self, name: &str) -> Option<String> {
+        let path = find_in_path(name, self.vars.get("PATH"))?;
+        self.command_hash().insert(name, &path, 0);
+        Some(path)
+    }
+}
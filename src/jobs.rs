@@ -0,0 +1,538 @@
+use nix::fcntl::{open, OFlag};
+use nix::sys::signal::{kill, Signal};
+use nix::sys::stat::Mode;
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{close, dup2, getpgrp, getpid, isatty, setpgid, tcgetpgrp, tcsetpgrp, Pid};
+
+use crate::output::Output;
+use crate::shell::Shell;
+use crate::signals;
+
+/// State of one process of a job, as last reported by `waitpid`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    Running,
+    Stopped(Signal),
+    Exited(i32),
+    /// Killed by a signal, and whether it dumped core
+    Signaled(Signal, bool),
+}
+
+impl ProcessState {
+    fn from_wait_status(status: WaitStatus) -> Option<Self> {
+        match status {
+            WaitStatus::Exited(_, code) => Some(ProcessState::Exited(code)),
+            WaitStatus::Signaled(_, signal, core) => Some(ProcessState::Signaled(signal, core)),
+            WaitStatus::Stopped(_, signal) => Some(ProcessState::Stopped(signal)),
+            WaitStatus::Continued(_) => Some(ProcessState::Running),
+            _ => None,
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        matches!(self, ProcessState::Exited(_) | ProcessState::Signaled(..))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Process {
+    pub pid: Pid,
+    pub state: ProcessState,
+}
+
+/// A pipeline or background list that is started, stopped and resumed as a unit
+#[derive(Debug, Clone)]
+pub struct Job {
+    /// Number shown as `[n]` and used in `%n`, or 0 before the job enters the table
+    pub id: usize,
+    /// Process group of every process in the job
+    pub pgid: Pid,
+    pub processes: Vec<Process>,
+    /// Source text shown by `jobs`
+    pub command: String,
+    /// Whether a change of state still has to be reported
+    notify: bool,
+    /// When the job was last started, stopped or resumed, used to pick `%+` and `%-`
+    touched: u64,
+}
+
+impl Job {
+    pub fn new(pgid: Pid, pids: &[Pid], command: &str) -> Self {
+        Job {
+            id: 0,
+            pgid,
+            processes: pids
+                .iter()
+                .map(|&pid| Process {
+                    pid,
+                    state: ProcessState::Running,
+                })
+                .collect(),
+            command: command.to_string(),
+            notify: false,
+            touched: 0,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.processes
+            .iter()
+            .all(|process| process.state.is_finished())
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        !self.is_done()
+            && self
+                .processes
+                .iter()
+                .all(|process| !matches!(process.state, ProcessState::Running))
+    }
+
+    /// Process ID of the last command of the pipeline, as in `$!`
+    pub fn last_pid(&self) -> Pid {
+        self.processes
+            .last()
+            .map_or(self.pgid, |process| process.pid)
+    }
+
+    /// Exit status of the job: that of its last process
+    pub fn status(&self) -> i32 {
+        self.statuses().last().copied().unwrap_or(0)
+    }
+
+    /// The exit status of each process, using 128+n for signal n
+    pub fn statuses(&self) -> Vec<i32> {
+        self.processes
+            .iter()
+            .map(|process| match process.state {
+                ProcessState::Exited(code) => code,
+                ProcessState::Signaled(signal, _) | ProcessState::Stopped(signal) => {
+                    128 + signal as i32
+                }
+                ProcessState::Running => 0,
+            })
+            .collect()
+    }
+
+    fn update(&mut self, pid: Pid, state: ProcessState) {
+        if let Some(process) = self.processes.iter_mut().find(|process| process.pid == pid) {
+            if process.state != state {
+                process.state = state;
+                // Only finishing or stopping is reported, not being continued
+                self.notify = state != ProcessState::Running;
+            }
+        }
+    }
+
+    fn set_running(&mut self) {
+        for process in &mut self.processes {
+            if let ProcessState::Stopped(_) = process.state {
+                process.state = ProcessState::Running;
+            }
+        }
+    }
+
+    /// The state column of `jobs`, e.g. `Running`, `Stopped` or `Exit 1`
+    pub fn state_text(&self) -> String {
+        if !self.is_done() {
+            let stop_signal = self
+                .processes
+                .iter()
+                .find_map(|process| match process.state {
+                    ProcessState::Stopped(signal) => Some(signal),
+                    _ => None,
+                });
+            return match stop_signal {
+                Some(signal) if self.is_stopped() => match signal {
+                    Signal::SIGTTIN => "Stopped (tty input)".to_string(),
+                    Signal::SIGTTOU => "Stopped (tty output)".to_string(),
+                    Signal::SIGSTOP => "Stopped (signal)".to_string(),
+                    _ => "Stopped".to_string(),
+                },
+                _ => "Running".to_string(),
+            };
+        }
+        match self.processes.last().map(|process| process.state) {
+            Some(ProcessState::Signaled(signal, core)) => {
+                let mut text = signal_description(signal).to_string();
+                if core {
+                    text.push_str(" (core dumped)");
+                }
+                text
+            }
+            _ if self.status() == 0 => "Done".to_string(),
+            _ => format!("Exit {}", self.status()),
+        }
+    }
+}
+
+/// The description of a signal that killed or stopped a process, as bash prints it
+pub fn signal_description(signal: Signal) -> &'static str {
+    match signal {
+        Signal::SIGHUP => "Hangup",
+        Signal::SIGINT => "Interrupt",
+        Signal::SIGQUIT => "Quit",
+        Signal::SIGILL => "Illegal instruction",
+        Signal::SIGTRAP => "Trace/breakpoint trap",
+        Signal::SIGABRT => "Aborted",
+        Signal::SIGBUS => "Bus error",
+        Signal::SIGFPE => "Floating point exception",
+        Signal::SIGKILL => "Killed",
+        Signal::SIGUSR1 => "User defined signal 1",
+        Signal::SIGSEGV => "Segmentation fault",
+        Signal::SIGUSR2 => "User defined signal 2",
+        Signal::SIGPIPE => "Broken pipe",
+        Signal::SIGALRM => "Alarm clock",
+        Signal::SIGTERM => "Terminated",
+        Signal::SIGSTOP => "Stopped (signal)",
+        Signal::SIGTSTP => "Stopped",
+        Signal::SIGTTIN => "Stopped (tty input)",
+        Signal::SIGTTOU => "Stopped (tty output)",
+        Signal::SIGXCPU => "CPU time limit exceeded",
+        Signal::SIGXFSZ => "File size limit exceeded",
+        Signal::SIGVTALRM => "Virtual timer expired",
+        Signal::SIGPROF => "Profiling timer expired",
+        Signal::SIGSYS => "Bad system call",
+        _ => signal.as_str(),
+    }
+}
+
+/// Jobs started in the background or stopped, numbered from 1
+#[derive(Debug, Default)]
+pub struct JobTable {
+    jobs: Vec<Job>,
+    clock: u64,
+}
+
+impl JobTable {
+    /// Add a job, keeping its number if it already had one, and make it the current job
+    pub fn insert(&mut self, mut job: Job) -> usize {
+        if job.id == 0 {
+            job.id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        }
+        self.clock += 1;
+        job.touched = self.clock;
+        let id = job.id;
+        let index = self.jobs.partition_point(|other| other.id < id);
+        self.jobs.insert(index, job);
+        id
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Job> {
+        self.jobs.iter().find(|job| job.id == id)
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Job> {
+        let index = self.jobs.iter().position(|job| job.id == id)?;
+        Some(self.jobs.remove(index))
+    }
+
+    pub fn ids(&self) -> Vec<usize> {
+        self.jobs.iter().map(|job| job.id).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    /// Job numbers by preference for `%+`: stopped jobs first, then the most recently used
+    fn ranked(&self) -> Vec<usize> {
+        let mut jobs: Vec<&Job> = self.jobs.iter().collect();
+        jobs.sort_by_key(|job| (!job.is_stopped(), std::cmp::Reverse(job.touched)));
+        jobs.iter().map(|job| job.id).collect()
+    }
+
+    /// The job `%+` refers to
+    pub fn current(&self) -> Option<usize> {
+        self.ranked().first().copied()
+    }
+
+    /// The job `%-` refers to
+    pub fn previous(&self) -> Option<usize> {
+        self.ranked().get(1).copied()
+    }
+
+    /// Resolve a job spec: `%n`, `%+`, `%%`, `%-`, `%string` or `%?string`
+    pub fn find(&self, spec: &str) -> Result<usize, String> {
+        let name = spec.strip_prefix('%').unwrap_or(spec);
+        let found = match name {
+            "" | "+" | "%" => return self.current().ok_or_else(|| "current: no such job".into()),
+            "-" => {
+                return self
+                    .previous()
+                    .ok_or_else(|| format!("{}: no such job", spec))
+            }
+            _ if name.chars().all(|ch| ch.is_ascii_digit()) => name
+                .parse()
+                .ok()
+                .filter(|&id| self.get(id).is_some())
+                .map(|id| vec![id]),
+            _ => {
+                let matching: Vec<usize> = self
+                    .jobs
+                    .iter()
+                    .filter(|job| match name.strip_prefix('?') {
+                        Some(text) => job.command.contains(text),
+                        None => job.command.starts_with(name),
+                    })
+                    .map(|job| job.id)
+                    .collect();
+                Some(matching)
+            }
+        };
+        match found.as_deref() {
+            Some([id]) => Ok(*id),
+            Some([_, _, ..]) => Err(format!("{}: ambiguous job spec", spec)),
+            _ => Err(format!("{}: no such job", spec)),
+        }
+    }
+
+    /// Collect status changes of every job without blocking
+    pub fn reap(&mut self) {
+        let flags = WaitPidFlag::WNOHANG | WaitPidFlag::WUNTRACED | WaitPidFlag::WCONTINUED;
+        for job in &mut self.jobs {
+            let pids: Vec<Pid> = job
+                .processes
+                .iter()
+                .filter(|process| !process.state.is_finished())
+                .map(|process| process.pid)
+                .collect();
+            for pid in pids {
+                while let Ok(status) = waitpid(pid, Some(flags)) {
+                    match ProcessState::from_wait_status(status) {
+                        Some(state) => job.update(pid, state),
+                        None => break,
+                    }
+                }
+            }
+        }
+    }
+
+    /// A line of `jobs` output, with the process ID of each process for `jobs -l`
+    pub fn format(&self, id: usize, long: bool) -> String {
+        let job = match self.get(id) {
+            Some(job) => job,
+            None => return String::new(),
+        };
+        let marker = if self.current() == Some(id) {
+            '+'
+        } else if self.previous() == Some(id) {
+            '-'
+        } else {
+            ' '
+        };
+        let background = if job.is_done() || job.is_stopped() {
+            ""
+        } else {
+            " &"
+        };
+        if long {
+            // The other processes of a pipeline follow on lines of their own
+            let mut lines = vec![format!(
+                "[{}]{} {} {:<24}{}{}",
+                id,
+                marker,
+                job.pgid,
+                job.state_text(),
+                job.command,
+                background
+            )];
+            for process in job.processes.iter().skip(1) {
+                lines.push(format!("     {}", process.pid));
+            }
+            return lines.join("\n");
+        }
+        format!(
+            "[{}]{}  {:<24}{}{}",
+            id,
+            marker,
+            job.state_text(),
+            job.command,
+            background
+        )
+    }
+}
+
+impl Shell {
+    /// Become an interactive shell that ignores terminal signals and, on a
+    /// terminal, takes control of it so that jobs can be moved between the
+    /// foreground and the background
+    pub fn init_interactive(&mut self) {
+        self.interactive = true;
+        let on_terminal = isatty(0).unwrap_or(false);
+        // Wait until the shell is in the foreground of its terminal
+        while let Ok(foreground) = tcgetpgrp(0) {
+            if !on_terminal || foreground == getpgrp() {
+                break;
+            }
+            let _ = kill(Pid::from_raw(-getpgrp().as_raw()), Signal::SIGTTIN);
+        }
+        signals::ignore_interactive_signals();
+        if !on_terminal {
+            return;
+        }
+        let pid = getpid();
+        let _ = setpgid(pid, pid);
+        let _ = tcsetpgrp(0, pid);
+        self.job_control = true;
+    }
+
+    /// Set up a forked child of this shell. With job control it joins process
+    /// group `pgid` (a new one of its own for `None`), taking the terminal if
+    /// it runs in the foreground.
+    pub fn enter_child(&mut self, pgid: Option<Pid>, foreground: bool) {
+        let job_control = self.job_control;
+        if job_control {
+            let pid = getpid();
+            let pgid = pgid.unwrap_or(pid);
+            let _ = setpgid(pid, pgid);
+            if foreground {
+                let _ = tcsetpgrp(0, pgid);
+            }
+        }
+        self.enter_subshell();
+        if !job_control && !foreground {
+            // Without job control, background jobs must not read the shell's
+            // input nor be interrupted from the terminal
+            if let Ok(null) = open("/dev/null", OFlag::O_RDONLY, Mode::empty()) {
+                let _ = dup2(null, 0);
+                let _ = close(null);
+            }
+            signals::ignore_background_interrupts();
+        }
+    }
+
+    /// A subshell does no job control of its own. It keeps a copy of the job
+    /// table so that `$(jobs -p)` still lists the parent's jobs.
+    pub fn enter_subshell(&mut self) {
+        if self.interactive {
+            signals::restore_default_signals();
+        }
+        // The shell's own writes to a closed pipe fail instead of killing it,
+        // but the commands it runs should be killed
+        signals::reset(Signal::SIGPIPE, false);
+        self.traps.reset_for_subshell();
+        self.job_control = false;
+    }
+
+    /// Put a forked child in process group `pgid` from the parent's side too,
+    /// so that it is there whichever process runs first
+    pub fn set_child_process_group(&self, child: Pid, pgid: Pid) {
+        if self.job_control {
+            let _ = setpgid(child, pgid);
+        }
+    }
+
+    /// Give `job` the terminal and wait until it finishes or stops, returning its
+    /// exit status. A stopped job is kept in the job table.
+    pub fn wait_for_foreground(&mut self, job: Job) -> i32 {
+        self.wait_for_pipeline(job).last().copied().unwrap_or(0)
+    }
+
+    /// Like `wait_for_foreground`, returning the status of every process in the job
+    pub fn wait_for_pipeline(&mut self, mut job: Job) -> Vec<i32> {
+        if self.job_control {
+            let _ = tcsetpgrp(0, job.pgid);
+        }
+        // Without job control, as in a subshell, a stopped command is waited for until it ends
+        let flags = self.job_control.then_some(WaitPidFlag::WUNTRACED);
+        for i in 0..job.processes.len() {
+            let pid = job.processes[i].pid;
+            while !job.processes[i].state.is_finished() {
+                match waitpid(pid, flags) {
+                    Ok(status) => match ProcessState::from_wait_status(status) {
+                        Some(state @ ProcessState::Stopped(_)) => {
+                            job.update(pid, state);
+                            break;
+                        }
+                        Some(state) => job.update(pid, state),
+                        None => {}
+                    },
+                    Err(nix::errno::Errno::EINTR) => continue,
+                    Err(_) => {
+                        job.update(pid, ProcessState::Exited(1));
+                    }
+                }
+            }
+        }
+        if self.job_control {
+            let _ = tcsetpgrp(0, getpgrp());
+        }
+
+        let statuses = job.statuses();
+        if let Some(ProcessState::Signaled(signal, _)) = job.processes.last().map(|p| p.state) {
+            match signal {
+                // The terminal already echoed `^C`, so only the line is ended
+                Signal::SIGINT if self.interactive => eprintln!(),
+                Signal::SIGINT | Signal::SIGPIPE => {}
+                _ => eprintln!("{}", job.state_text()),
+            }
+        }
+        if job.is_stopped() {
+            job.notify = false;
+            let id = self.jobs.insert(job);
+            eprintln!();
+            eprintln!("{}", self.jobs.format(id, false));
+        }
+        statuses
+    }
+
+    /// Record a job started with `&`, announcing it in an interactive shell
+    pub fn start_background(&mut self, job: Job) {
+        self.last_background_pid = Some(job.last_pid().as_raw());
+        let pid = job.last_pid();
+        let id = self.jobs.insert(job);
+        if self.interactive {
+            eprintln!("[{}] {}", id, pid);
+        }
+    }
+
+    /// Continue a stopped job, in the foreground or in the background
+    pub fn continue_job(&mut self, id: usize, foreground: bool, out: &mut Output) -> i32 {
+        let mut job = match self.jobs.remove(id) {
+            Some(job) => job,
+            None => return 1,
+        };
+        job.set_running();
+        if foreground {
+            writeln!(out, "{}", job.command);
+            if self.job_control {
+                let _ = tcsetpgrp(0, job.pgid);
+            }
+            let _ = kill(Pid::from_raw(-job.pgid.as_raw()), Signal::SIGCONT);
+            self.wait_for_foreground(job)
+        } else {
+            let _ = kill(Pid::from_raw(-job.pgid.as_raw()), Signal::SIGCONT);
+            let command = job.command.clone();
+            let id = self.jobs.insert(job);
+            let marker = if self.jobs.current() == Some(id) {
+                '+'
+            } else if self.jobs.previous() == Some(id) {
+                '-'
+            } else {
+                ' '
+            };
+            writeln!(out, "[{}]{} {} &", id, marker, command);
+            0
+        }
+    }
+
+    /// Report jobs that finished or stopped since the last prompt, and forget finished ones
+    pub fn notify_jobs(&mut self) {
+        self.jobs.reap();
+        for id in self.jobs.ids() {
+            let (done, notify) = match self.jobs.get(id) {
+                Some(job) => (job.is_done(), job.notify),
+                None => continue,
+            };
+            if notify && self.interactive {
+                eprintln!("{}", self.jobs.format(id, false));
+            }
+            if done {
+                self.jobs.remove(id);
+            } else if let Some(job) = self.jobs.jobs.iter_mut().find(|job| job.id == id) {
+                job.notify = false;
+            }
+        }
+    }
+}
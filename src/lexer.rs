@@ -0,0 +1,485 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::ast::HereDoc;
+use crate::parser::{find_command_substitution_end, ParseError};
+
+/// Control operators that separate or group commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Pipe,
+    Or,
+    Background,
+    And,
+    Semicolon,
+    DoubleSemicolon,
+    /// `;&` ends a case item and falls through to the next one
+    SemicolonAnd,
+    /// `;;&` ends a case item and keeps testing the following patterns
+    DoubleSemicolonAnd,
+    LeftParen,
+    RightParen,
+}
+
+impl Operator {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Operator::Pipe => "|",
+            Operator::Or => "||",
+            Operator::Background => "&",
+            Operator::And => "&&",
+            Operator::Semicolon => ";",
+            Operator::DoubleSemicolon => ";;",
+            Operator::SemicolonAnd => ";&",
+            Operator::DoubleSemicolonAnd => ";;&",
+            Operator::LeftParen => "(",
+            Operator::RightParen => ")",
+        }
+    }
+}
+
+/// Redirection operators, e.g. `>` or `2>>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectOp {
+    Input,
+    Output,
+    Append,
+    Clobber,
+    ReadWrite,
+    DupInput,
+    DupOutput,
+    HereDoc,
+    HereDocStrip,
+    HereString,
+    OutputBoth,
+    AppendBoth,
+}
+
+impl RedirectOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RedirectOp::Input => "<",
+            RedirectOp::Output => ">",
+            RedirectOp::Append => ">>",
+            RedirectOp::Clobber => ">|",
+            RedirectOp::ReadWrite => "<>",
+            RedirectOp::DupInput => "<&",
+            RedirectOp::DupOutput => ">&",
+            RedirectOp::HereDoc => "<<",
+            RedirectOp::HereDocStrip => "<<-",
+            RedirectOp::HereString => "<<<",
+            RedirectOp::OutputBoth => "&>",
+            RedirectOp::AppendBoth => "&>>",
+        }
+    }
+}
+
+// Longest operators first so that `&&` wins over `&`
+const OPERATORS: &[Operator] = &[
+    Operator::DoubleSemicolonAnd,
+    Operator::Or,
+    Operator::And,
+    Operator::DoubleSemicolon,
+    Operator::SemicolonAnd,
+    Operator::Pipe,
+    Operator::Background,
+    Operator::Semicolon,
+    Operator::LeftParen,
+    Operator::RightParen,
+];
+
+const REDIRECT_OPS: &[RedirectOp] = &[
+    RedirectOp::AppendBoth,
+    RedirectOp::OutputBoth,
+    RedirectOp::HereString,
+    RedirectOp::HereDocStrip,
+    RedirectOp::HereDoc,
+    RedirectOp::Append,
+    RedirectOp::Clobber,
+    RedirectOp::ReadWrite,
+    RedirectOp::DupInput,
+    RedirectOp::DupOutput,
+    RedirectOp::Input,
+    RedirectOp::Output,
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    /// A word exactly as written, quotes and backslashes included
+    Word(String),
+    Operator(Operator),
+    Redirect(RedirectOp),
+    /// The file descriptor number directly in front of a redirection, as in `2>`
+    IoNumber(i32),
+    /// The expression of a `(( ... ))` command
+    Arithmetic(String),
+    Newline,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{}", word),
+            Token::Operator(op) => write!(f, "{}", op.as_str()),
+            Token::Redirect(op) => write!(f, "{}", op.as_str()),
+            Token::IoNumber(fd) => write!(f, "{}", fd),
+            Token::Arithmetic(expression) => write!(f, "(({}))", expression),
+            Token::Newline => write!(f, "newline"),
+        }
+    }
+}
+
+/// Find the `}` closing a `${` whose contents start at `chars[start]`,
+/// skipping over quoted text and nested braces
+pub fn find_closing_brace(chars: &[char], start: usize) -> Option<usize> {
+    let mut depth = 1;
+    let mut in_single_quotes = false;
+    let mut in_double_quotes = false;
+    let mut i = start;
+
+    while i < chars.len() {
+        let ch = chars[i];
+        if in_single_quotes {
+            if ch == '\'' {
+                in_single_quotes = false;
+            }
+        } else {
+            match ch {
+                '\\' => i += 1,
+                '\'' if !in_double_quotes => in_single_quotes = true,
+                '"' => in_double_quotes = !in_double_quotes,
+                '{' if !in_double_quotes => depth += 1,
+                '}' if !in_double_quotes => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(i);
+                    }
+                }
+                _ => {}
+            }
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Find the `))` closing an arithmetic expression that starts at `chars[start]`,
+/// returning the index of its first `)`. Returns `None` if the parentheses do
+/// not balance that way, as in `$( (cmd) )`.
+pub fn find_arithmetic_end(chars: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            ')' => return (chars.get(i + 1) == Some(&')')).then_some(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Find the unescaped `` ` `` closing a command substitution whose text starts at `chars[start]`
+pub fn find_closing_backquote(chars: &[char], start: usize) -> Option<usize> {
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '`' => return Some(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+fn is_blank(ch: char) -> bool {
+    ch == ' ' || ch == '\t'
+}
+
+fn is_operator_start(ch: char) -> bool {
+    matches!(ch, '|' | '&' | ';' | '(' | ')' | '<' | '>')
+}
+
+/// A here-document whose body starts after the next newline
+struct PendingHereDoc {
+    delimiter: String,
+    strip_tabs: bool,
+    here_doc: Rc<HereDoc>,
+}
+
+pub struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+    pending_here_docs: Vec<PendingHereDoc>,
+}
+
+impl Lexer {
+    pub fn new(input: &str) -> Self {
+        Lexer {
+            chars: input.chars().collect(),
+            pos: 0,
+            pending_here_docs: Vec::new(),
+        }
+    }
+
+    /// Start lexing a copy of `chars` at `pos`, as used for nested command substitutions
+    pub fn at(chars: &[char], pos: usize) -> Self {
+        Lexer {
+            chars: chars.to_vec(),
+            pos,
+            pending_here_docs: Vec::new(),
+        }
+    }
+
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Register a here-document whose body is read when the current line ends
+    pub fn push_here_doc(&mut self, delimiter: String, strip_tabs: bool, here_doc: Rc<HereDoc>) {
+        self.pending_here_docs.push(PendingHereDoc {
+            delimiter,
+            strip_tabs,
+            here_doc,
+        });
+    }
+
+    /// Read the bodies of all pending here-documents, one line at a time
+    fn read_here_doc_bodies(&mut self) -> Result<(), ParseError> {
+        for pending in std::mem::take(&mut self.pending_here_docs) {
+            let mut body = String::new();
+            loop {
+                if self.pos >= self.chars.len() {
+                    return Err(ParseError::UnterminatedHereDoc(pending.delimiter));
+                }
+
+                let start = self.pos;
+                while self.pos < self.chars.len() && self.chars[self.pos] != '\n' {
+                    self.pos += 1;
+                }
+                let mut line: String = self.chars[start..self.pos].iter().collect();
+                // Step over the newline itself
+                self.pos += 1;
+
+                if pending.strip_tabs {
+                    line = line.trim_start_matches('\t').to_string();
+                }
+                if line == pending.delimiter {
+                    break;
+                }
+                body.push_str(&line);
+                body.push('\n');
+            }
+            *pending.here_doc.body.borrow_mut() = body;
+        }
+        Ok(())
+    }
+
+    fn peek_char(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn starts_with(&self, text: &str) -> bool {
+        text.chars()
+            .enumerate()
+            .all(|(i, ch)| self.chars.get(self.pos + i) == Some(&ch))
+    }
+
+    /// Return the next token, or `None` at the end of input
+    pub fn next_token(&mut self) -> Result<Option<Token>, ParseError> {
+        self.skip_blanks_and_comments();
+
+        let ch = match self.peek_char() {
+            Some(ch) => ch,
+            None => {
+                if let Some(pending) = self.pending_here_docs.first() {
+                    return Err(ParseError::UnterminatedHereDoc(pending.delimiter.clone()));
+                }
+                return Ok(None);
+            }
+        };
+
+        if ch == '\n' {
+            self.pos += 1;
+            self.read_here_doc_bodies()?;
+            return Ok(Some(Token::Newline));
+        }
+
+        if self.starts_with("((") {
+            if let Some(end) = find_arithmetic_end(&self.chars, self.pos + 2) {
+                let expression = self.chars[self.pos + 2..end].iter().collect();
+                self.pos = end + 2;
+                return Ok(Some(Token::Arithmetic(expression)));
+            }
+        }
+
+        if is_operator_start(ch) {
+            for op in REDIRECT_OPS {
+                if self.starts_with(op.as_str()) {
+                    self.pos += op.as_str().len();
+                    return Ok(Some(Token::Redirect(*op)));
+                }
+            }
+            for op in OPERATORS {
+                if self.starts_with(op.as_str()) {
+                    self.pos += op.as_str().len();
+                    return Ok(Some(Token::Operator(*op)));
+                }
+            }
+        }
+
+        let word = self.read_word()?;
+
+        // A plain number directly followed by `<` or `>` names a file descriptor
+        if !word.is_empty()
+            && word.chars().all(|c| c.is_ascii_digit())
+            && matches!(self.peek_char(), Some('<') | Some('>'))
+        {
+            if let Ok(fd) = word.parse::<i32>() {
+                return Ok(Some(Token::IoNumber(fd)));
+            }
+        }
+
+        Ok(Some(Token::Word(word)))
+    }
+
+    fn skip_blanks_and_comments(&mut self) {
+        while let Some(ch) = self.peek_char() {
+            if is_blank(ch) {
+                self.pos += 1;
+            } else if ch == '\\' && self.chars.get(self.pos + 1) == Some(&'\n') {
+                // Line continuation
+                self.pos += 2;
+            } else if ch == '#' {
+                while let Some(ch) = self.peek_char() {
+                    if ch == '\n' {
+                        break;
+                    }
+                    self.pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Copy a `${...}` expansion into `word`, which may contain blanks and operators
+    fn read_parameter_expansion(&mut self, word: &mut String) -> Result<(), ParseError> {
+        let end = find_closing_brace(&self.chars, self.pos + 2)
+            .ok_or(ParseError::UnterminatedQuote('}'))?;
+        word.extend(&self.chars[self.pos..=end]);
+        self.pos = end + 1;
+        Ok(())
+    }
+
+    /// Copy a `$(...)` command substitution or `$((...))` arithmetic expansion into `word`
+    fn read_command_substitution(&mut self, word: &mut String) -> Result<(), ParseError> {
+        if self.chars.get(self.pos + 2) == Some(&'(') {
+            if let Some(end) = find_arithmetic_end(&self.chars, self.pos + 3) {
+                word.extend(&self.chars[self.pos..end + 2]);
+                self.pos = end + 2;
+                return Ok(());
+            }
+        }
+        let end = find_command_substitution_end(&self.chars, self.pos + 2)?;
+        word.extend(&self.chars[self.pos..=end]);
+        self.pos = end + 1;
+        Ok(())
+    }
+
+    /// Copy a `` `...` `` command substitution into `word`
+    fn read_backquoted(&mut self, word: &mut String) -> Result<(), ParseError> {
+        let end = find_closing_backquote(&self.chars, self.pos + 1)
+            .ok_or(ParseError::UnterminatedQuote('`'))?;
+        word.extend(&self.chars[self.pos..=end]);
+        self.pos = end + 1;
+        Ok(())
+    }
+
+    /// Read a word up to the next unquoted blank, newline or operator
+    fn read_word(&mut self) -> Result<String, ParseError> {
+        let mut word = String::new();
+
+        while let Some(ch) = self.peek_char() {
+            match ch {
+                '\\' => {
+                    self.pos += 1;
+                    match self.peek_char() {
+                        Some('\n') => self.pos += 1,
+                        Some(next_ch) => {
+                            word.push('\\');
+                            word.push(next_ch);
+                            self.pos += 1;
+                        }
+                        None => word.push('\\'),
+                    }
+                }
+                '\'' => {
+                    word.push(ch);
+                    self.pos += 1;
+                    loop {
+                        match self.peek_char() {
+                            Some(next_ch) => {
+                                word.push(next_ch);
+                                self.pos += 1;
+                                if next_ch == '\'' {
+                                    break;
+                                }
+                            }
+                            None => return Err(ParseError::UnterminatedQuote('\'')),
+                        }
+                    }
+                }
+                '"' => {
+                    word.push(ch);
+                    self.pos += 1;
+                    loop {
+                        match self.peek_char() {
+                            Some('\\') => {
+                                word.push('\\');
+                                self.pos += 1;
+                                if let Some(next_ch) = self.peek_char() {
+                                    word.push(next_ch);
+                                    self.pos += 1;
+                                }
+                            }
+                            Some('$') if self.chars.get(self.pos + 1) == Some(&'{') => {
+                                self.read_parameter_expansion(&mut word)?;
+                            }
+                            Some('$') if self.chars.get(self.pos + 1) == Some(&'(') => {
+                                self.read_command_substitution(&mut word)?;
+                            }
+                            Some('`') => self.read_backquoted(&mut word)?,
+                            Some(next_ch) => {
+                                word.push(next_ch);
+                                self.pos += 1;
+                                if next_ch == '"' {
+                                    break;
+                                }
+                            }
+                            None => return Err(ParseError::UnterminatedQuote('"')),
+                        }
+                    }
+                }
+                '$' if self.chars.get(self.pos + 1) == Some(&'{') => {
+                    self.read_parameter_expansion(&mut word)?;
+                }
+                '$' if self.chars.get(self.pos + 1) == Some(&'(') => {
+                    self.read_command_substitution(&mut word)?;
+                }
+                '`' => self.read_backquoted(&mut word)?,
+                ch if is_blank(ch) || ch == '\n' || is_operator_start(ch) => break,
+                _ => {
+                    word.push(ch);
+                    self.pos += 1;
+                }
+            }
+        }
+
+        Ok(word)
+    }
+}
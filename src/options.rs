@@ -0,0 +1,129 @@
+/// Optional shell behavior toggled with `shopt -s` and `shopt -u`
+#[derive(Debug, Clone, Default)]
+pub struct ShoptOptions {
+    /// Patterns match hidden files without a leading `.`
+    pub dotglob: bool,
+    /// Patterns that match nothing are an error
+    pub failglob: bool,
+    /// `**` matches files in all subdirectories
+    pub globstar: bool,
+    /// The last command of a pipeline runs in the shell itself when job control is off
+    pub lastpipe: bool,
+    /// Patterns that match nothing expand to nothing
+    pub nullglob: bool,
+}
+
+impl ShoptOptions {
+    pub const NAMES: [&'static str; 5] =
+        ["dotglob", "failglob", "globstar", "lastpipe", "nullglob"];
+
+    pub fn get(&self, name: &str) -> Option<bool> {
+        match name {
+            "dotglob" => Some(self.dotglob),
+            "failglob" => Some(self.failglob),
+            "globstar" => Some(self.globstar),
+            "lastpipe" => Some(self.lastpipe),
+            "nullglob" => Some(self.nullglob),
+            _ => None,
+        }
+    }
+
+    /// Turn an option on or off, returning `false` for an unknown name
+    pub fn set(&mut self, name: &str, value: bool) -> bool {
+        let option = match name {
+            "dotglob" => &mut self.dotglob,
+            "failglob" => &mut self.failglob,
+            "globstar" => &mut self.globstar,
+            "lastpipe" => &mut self.lastpipe,
+            "nullglob" => &mut self.nullglob,
+            _ => return false,
+        };
+        *option = value;
+        true
+    }
+}
+
+/// Shell options toggled with `set -o name` and `set +o name`, most of them
+/// also with a single letter as in `set -e`
+#[derive(Debug, Clone, Default)]
+pub struct SetOptions {
+    /// `-e`: exit when a command fails outside of a condition
+    pub errexit: bool,
+    /// `-C`: `>` does not overwrite existing regular files
+    pub noclobber: bool,
+    /// `-n`: read commands without executing them
+    pub noexec: bool,
+    /// `-f`: no pathname expansion
+    pub noglob: bool,
+    /// `-u`: expanding an unset parameter is an error
+    pub nounset: bool,
+    /// A pipeline fails with the status of its rightmost failing command
+    pub pipefail: bool,
+    /// `-v`: print input lines as they are read
+    pub verbose: bool,
+    /// `-x`: print each command with its expanded arguments before executing it
+    pub xtrace: bool,
+}
+
+impl SetOptions {
+    pub const NAMES: [&'static str; 8] = [
+        "errexit",
+        "noclobber",
+        "noexec",
+        "noglob",
+        "nounset",
+        "pipefail",
+        "verbose",
+        "xtrace",
+    ];
+
+    /// Options that also have a single letter
+    pub const LETTERS: [(char, &'static str); 7] = [
+        ('e', "errexit"),
+        ('f', "noglob"),
+        ('n', "noexec"),
+        ('u', "nounset"),
+        ('v', "verbose"),
+        ('x', "xtrace"),
+        ('C', "noclobber"),
+    ];
+
+    /// The option named by a single letter
+    pub fn letter_name(letter: char) -> Option<&'static str> {
+        Self::LETTERS
+            .iter()
+            .find(|(option_letter, _)| *option_letter == letter)
+            .map(|(_, name)| *name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<bool> {
+        match name {
+            "errexit" => Some(self.errexit),
+            "noclobber" => Some(self.noclobber),
+            "noexec" => Some(self.noexec),
+            "noglob" => Some(self.noglob),
+            "nounset" => Some(self.nounset),
+            "pipefail" => Some(self.pipefail),
+            "verbose" => Some(self.verbose),
+            "xtrace" => Some(self.xtrace),
+            _ => None,
+        }
+    }
+
+    /// Turn an option on or off, returning `false` for an unknown name
+    pub fn set(&mut self, name: &str, value: bool) -> bool {
+        let option = match name {
+            "errexit" => &mut self.errexit,
+            "noclobber" => &mut self.noclobber,
+            "noexec" => &mut self.noexec,
+            "noglob" => &mut self.noglob,
+            "nounset" => &mut self.nounset,
+            "pipefail" => &mut self.pipefail,
+            "verbose" => &mut self.verbose,
+            "xtrace" => &mut self.xtrace,
+            _ => return false,
+        };
+        *option = value;
+        true
+    }
+}
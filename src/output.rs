@@ -0,0 +1,54 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::mem::ManuallyDrop;
+use std::os::unix::io::FromRawFd;
+
+/// Where a builtin writes: the shell's stdout, wherever redirections and pipes
+/// point it. Writes go through `write!`/`writeln!`, and the first one that
+/// fails is kept so the builtin can be reported as failing once it is done.
+/// Nothing is buffered, so output that could not be written is not written
+/// later to wherever stdout points then.
+pub struct Output {
+    error: Option<io::Error>,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Output {
+    pub fn new() -> Self {
+        // Whatever the shell printed before comes first
+        let _ = io::stdout().flush();
+        Output { error: None }
+    }
+
+    /// Called by `write!` and `writeln!`; output after a failed write is dropped
+    pub fn write_fmt(&mut self, args: fmt::Arguments) {
+        match args.as_str() {
+            Some(text) => self.write_bytes(text.as_bytes()),
+            None => self.write_bytes(fmt::format(args).as_bytes()),
+        }
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        if self.error.is_none() {
+            // Descriptor 1 stays open; it belongs to the shell, not to this handle
+            let mut stdout = ManuallyDrop::new(unsafe { File::from_raw_fd(1) });
+            if let Err(e) = stdout.write_all(bytes) {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    /// Finish writing, returning the first error on the way
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,609 @@
+use std::rc::Rc;
+use thiserror::Error;
+
+use crate::ast::{
+    AndOrList, AndOrOp, Assignment, CaseItem, CaseTerminator, Command, CompoundCommand, HereDoc,
+    List, Pipeline, Redirection, SimpleCommand, Word,
+};
+use crate::lexer::{Lexer, Operator, RedirectOp, Token};
+use crate::variables::is_valid_name;
+
+/// Reserved words that can only appear inside a compound command
+const RESERVED_WORDS: [&str; 8] = ["then", "elif", "else", "fi", "do", "done", "esac", "}"];
+
+/// Every word with a meaning to the parser at the start of a command, as `type` reports them
+pub const KEYWORDS: [&str; 17] = [
+    "!", "case", "do", "done", "elif", "else", "esac", "fi", "for", "function", "if", "in", "then",
+    "until", "while", "{", "}",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParseError {
+    #[error("syntax error near unexpected token `{0}'")]
+    UnexpectedToken(String),
+    #[error("syntax error: unexpected end of file")]
+    UnexpectedEof,
+    #[error("unexpected EOF while looking for matching `{0}'")]
+    UnterminatedQuote(char),
+    #[error("here-document delimited by end-of-file (wanted `{0}')")]
+    UnterminatedHereDoc(String),
+}
+
+impl ParseError {
+    /// Whether more input could complete the command, e.g. an open quote
+    pub fn is_incomplete(&self) -> bool {
+        matches!(
+            self,
+            ParseError::UnexpectedEof
+                | ParseError::UnterminatedQuote(_)
+                | ParseError::UnterminatedHereDoc(_)
+        )
+    }
+}
+
+/// Parse a complete line (or several lines) of input into a command list
+pub fn parse(input: &str) -> Result<List, ParseError> {
+    Parser::new(input).parse_list()
+}
+
+/// Find the `)` closing a `$(` whose commands start at `chars[start]`.
+/// The commands are parsed so that parentheses in quotes, comments and
+/// here-documents are skipped correctly.
+pub fn find_command_substitution_end(chars: &[char], start: usize) -> Result<usize, ParseError> {
+    let mut parser = Parser {
+        lexer: Lexer::at(chars, start),
+        peeked: None,
+    };
+    let is_right_paren = |token: &Token| *token == Token::Operator(Operator::RightParen);
+    parser.parse_list_until(is_right_paren)?;
+    match parser.next()? {
+        Some(token) if is_right_paren(&token) => Ok(parser.lexer.position() - 1),
+        Some(token) => Err(ParseError::UnexpectedToken(token.to_string())),
+        None => Err(ParseError::UnterminatedQuote(')')),
+    }
+}
+
+pub struct Parser {
+    lexer: Lexer,
+    peeked: Option<Token>,
+}
+
+impl Parser {
+    pub fn new(input: &str) -> Self {
+        Parser {
+            lexer: Lexer::new(input),
+            peeked: None,
+        }
+    }
+
+    fn peek(&mut self) -> Result<Option<&Token>, ParseError> {
+        if self.peeked.is_none() {
+            self.peeked = self.lexer.next_token()?;
+        }
+        Ok(self.peeked.as_ref())
+    }
+
+    fn next(&mut self) -> Result<Option<Token>, ParseError> {
+        match self.peeked.take() {
+            Some(token) => Ok(Some(token)),
+            None => self.lexer.next_token(),
+        }
+    }
+
+    fn skip_newlines(&mut self) -> Result<(), ParseError> {
+        while let Some(Token::Newline) = self.peek()? {
+            self.next()?;
+        }
+        Ok(())
+    }
+
+    fn unexpected(token: Option<Token>) -> ParseError {
+        match token {
+            Some(token) => ParseError::UnexpectedToken(token.to_string()),
+            None => ParseError::UnexpectedEof,
+        }
+    }
+
+    pub fn parse_list(&mut self) -> Result<List, ParseError> {
+        self.parse_list_until(|_| false)
+    }
+
+    /// Parse commands up to the end of input or the first token for which
+    /// `is_end` holds, which is left unconsumed
+    fn parse_list_until(&mut self, is_end: impl Fn(&Token) -> bool) -> Result<List, ParseError> {
+        let mut list = List::default();
+
+        self.skip_newlines()?;
+        while self.peek()?.is_some_and(|token| !is_end(token)) {
+            list.items.push(self.parse_and_or()?);
+            match self.peek()? {
+                None => break,
+                Some(Token::Newline) | Some(Token::Operator(Operator::Semicolon)) => {
+                    self.next()?;
+                    self.skip_newlines()?
+                }
+                Some(Token::Operator(Operator::Background)) => {
+                    self.next()?;
+                    if let Some(and_or) = list.items.last_mut() {
+                        and_or.background = true;
+                    }
+                    self.skip_newlines()?
+                }
+                Some(token) if is_end(token) => break,
+                _ => {
+                    let token = self.next()?;
+                    return Err(Self::unexpected(token));
+                }
+            }
+        }
+
+        Ok(list)
+    }
+
+    fn parse_and_or(&mut self) -> Result<AndOrList, ParseError> {
+        let first = self.parse_pipeline()?;
+        let mut rest = Vec::new();
+
+        loop {
+            let op = match self.peek()? {
+                Some(Token::Operator(Operator::And)) => AndOrOp::And,
+                Some(Token::Operator(Operator::Or)) => AndOrOp::Or,
+                _ => break,
+            };
+            self.next()?;
+            self.skip_newlines()?;
+            rest.push((op, self.parse_pipeline()?));
+        }
+
+        Ok(AndOrList {
+            first,
+            rest,
+            background: false,
+        })
+    }
+
+    fn parse_pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let negated = matches!(self.peek()?, Some(Token::Word(word)) if word == "!");
+        if negated {
+            self.next()?;
+        }
+        let mut commands = vec![self.parse_command()?];
+
+        while let Some(Token::Operator(Operator::Pipe)) = self.peek()? {
+            self.next()?;
+            self.skip_newlines()?;
+            commands.push(self.parse_command()?);
+        }
+
+        Ok(Pipeline { commands, negated })
+    }
+
+    fn parse_command(&mut self) -> Result<Command, ParseError> {
+        let keyword = match self.peek()? {
+            Some(Token::Word(word)) => Some(word.clone()),
+            _ => None,
+        };
+        let compound = match keyword.as_deref() {
+            Some("if") => self.parse_if()?,
+            Some("while") | Some("until") => self.parse_while()?,
+            Some("for") => self.parse_for()?,
+            Some("case") => self.parse_case()?,
+            Some("{") => {
+                self.next()?;
+                let body = self.parse_compound_list(&["}"])?;
+                self.expect_keyword("}")?;
+                CompoundCommand::BraceGroup(body)
+            }
+            Some("function") => return self.parse_function(),
+            Some(word) if RESERVED_WORDS.contains(&word) => {
+                return Err(Self::unexpected(self.next()?));
+            }
+            _ => match self.peek()? {
+                Some(Token::Arithmetic(expression)) => {
+                    let expression = Word::new(expression);
+                    self.next()?;
+                    CompoundCommand::Arithmetic(expression)
+                }
+                Some(Token::Operator(Operator::LeftParen)) => {
+                    self.next()?;
+                    let is_right_paren =
+                        |token: &Token| *token == Token::Operator(Operator::RightParen);
+                    let body = self.parse_list_until(is_right_paren)?;
+                    match self.next()? {
+                        Some(token) if is_right_paren(&token) && !body.items.is_empty() => {}
+                        token => return Err(Self::unexpected(token)),
+                    }
+                    CompoundCommand::Subshell(body)
+                }
+                _ => {
+                    let command = self.parse_simple_command()?;
+                    // `name()` starts a function definition
+                    if let Some(Token::Operator(Operator::LeftParen)) = self.peek()? {
+                        if let Some(name) = function_name(&command) {
+                            self.next()?;
+                            return self.parse_function_body(name);
+                        }
+                    }
+                    return Ok(Command::Simple(command));
+                }
+            },
+        };
+
+        let mut redirections = Vec::new();
+        while let Some(Token::IoNumber(_)) | Some(Token::Redirect(_)) = self.peek()? {
+            redirections.push(self.parse_redirection()?);
+        }
+        Ok(Command::Compound(compound, redirections))
+    }
+
+    /// `function name [()] compound-command`
+    fn parse_function(&mut self) -> Result<Command, ParseError> {
+        self.expect_keyword("function")?;
+        let name = match self.next()? {
+            Some(Token::Word(name)) if is_valid_function_name(&name) => name,
+            token => return Err(Self::unexpected(token)),
+        };
+        if let Some(Token::Operator(Operator::LeftParen)) = self.peek()? {
+            self.next()?;
+        } else {
+            self.skip_newlines()?;
+            return self.parse_function_compound(name);
+        }
+        self.parse_function_body(name)
+    }
+
+    /// The `)` and compound command after `name(`
+    fn parse_function_body(&mut self, name: String) -> Result<Command, ParseError> {
+        match self.next()? {
+            Some(Token::Operator(Operator::RightParen)) => {}
+            token => return Err(Self::unexpected(token)),
+        }
+        self.skip_newlines()?;
+        self.parse_function_compound(name)
+    }
+
+    fn parse_function_compound(&mut self, name: String) -> Result<Command, ParseError> {
+        match self.parse_command()? {
+            body @ Command::Compound(..) => Ok(Command::FunctionDefinition {
+                name,
+                body: Rc::new(body),
+            }),
+            Command::Simple(command) => Err(ParseError::UnexpectedToken(
+                command
+                    .words
+                    .first()
+                    .map_or_else(|| "newline".to_string(), |word| word.raw.clone()),
+            )),
+            Command::FunctionDefinition { name, .. } => Err(ParseError::UnexpectedToken(name)),
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), ParseError> {
+        match self.next()? {
+            Some(Token::Word(word)) if word == keyword => Ok(()),
+            token => Err(Self::unexpected(token)),
+        }
+    }
+
+    /// A non-empty list of commands ending before one of `keywords`
+    fn parse_compound_list(&mut self, keywords: &[&str]) -> Result<List, ParseError> {
+        let list = self.parse_list_until(|token| is_keyword(token, keywords))?;
+        if list.items.is_empty() {
+            return Err(Self::unexpected(self.next()?));
+        }
+        Ok(list)
+    }
+
+    /// `do list done`
+    fn parse_do_group(&mut self) -> Result<List, ParseError> {
+        self.expect_keyword("do")?;
+        let body = self.parse_compound_list(&["done"])?;
+        self.expect_keyword("done")?;
+        Ok(body)
+    }
+
+    fn parse_if(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.expect_keyword("if")?;
+        let mut branches = Vec::new();
+        let mut else_branch = None;
+
+        loop {
+            let condition = self.parse_compound_list(&["then"])?;
+            self.expect_keyword("then")?;
+            let body = self.parse_compound_list(&["elif", "else", "fi"])?;
+            branches.push((condition, body));
+
+            match self.next()? {
+                Some(Token::Word(word)) if word == "elif" => {}
+                Some(Token::Word(word)) if word == "else" => {
+                    else_branch = Some(self.parse_compound_list(&["fi"])?);
+                    self.expect_keyword("fi")?;
+                    break;
+                }
+                Some(Token::Word(word)) if word == "fi" => break,
+                token => return Err(Self::unexpected(token)),
+            }
+        }
+
+        Ok(CompoundCommand::If {
+            branches,
+            else_branch,
+        })
+    }
+
+    fn parse_while(&mut self) -> Result<CompoundCommand, ParseError> {
+        let until = matches!(self.next()?, Some(Token::Word(word)) if word == "until");
+        let condition = self.parse_compound_list(&["do"])?;
+        let body = self.parse_do_group()?;
+        Ok(if until {
+            CompoundCommand::Until { condition, body }
+        } else {
+            CompoundCommand::While { condition, body }
+        })
+    }
+
+    fn parse_for(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.expect_keyword("for")?;
+
+        let name = match self.next()? {
+            Some(Token::Arithmetic(expression)) => {
+                let parts: Vec<&str> = expression.split(';').collect();
+                let [init, condition, update] = parts.as_slice() else {
+                    return Err(ParseError::UnexpectedToken(format!("(({}))", expression)));
+                };
+                let (init, condition, update) =
+                    (Word::new(init), Word::new(condition), Word::new(update));
+                if let Some(Token::Operator(Operator::Semicolon)) = self.peek()? {
+                    self.next()?;
+                }
+                self.skip_newlines()?;
+                return Ok(CompoundCommand::ArithmeticFor {
+                    init,
+                    condition,
+                    update,
+                    body: self.parse_do_group()?,
+                });
+            }
+            Some(Token::Word(name)) if is_valid_name(&name) => name,
+            token => return Err(Self::unexpected(token)),
+        };
+
+        self.skip_newlines()?;
+        let words = match self.peek()? {
+            Some(Token::Word(word)) if word == "in" => {
+                self.next()?;
+                let mut words = Vec::new();
+                while let Some(Token::Word(word)) = self.peek()? {
+                    words.push(Word::new(word));
+                    self.next()?;
+                }
+                match self.next()? {
+                    Some(Token::Newline) | Some(Token::Operator(Operator::Semicolon)) => {}
+                    token => return Err(Self::unexpected(token)),
+                }
+                Some(words)
+            }
+            Some(Token::Operator(Operator::Semicolon)) => {
+                self.next()?;
+                None
+            }
+            _ => None,
+        };
+        self.skip_newlines()?;
+
+        Ok(CompoundCommand::For {
+            name,
+            words,
+            body: self.parse_do_group()?,
+        })
+    }
+
+    fn parse_case(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.expect_keyword("case")?;
+        let word = match self.next()? {
+            Some(Token::Word(word)) => Word::new(&word),
+            token => return Err(Self::unexpected(token)),
+        };
+        self.skip_newlines()?;
+        self.expect_keyword("in")?;
+        self.skip_newlines()?;
+
+        let mut items = Vec::new();
+        loop {
+            match self.peek()? {
+                Some(token) if is_keyword(token, &["esac"]) => {
+                    self.next()?;
+                    break;
+                }
+                Some(Token::Operator(Operator::LeftParen)) => {
+                    self.next()?;
+                }
+                _ => {}
+            }
+
+            let mut patterns = Vec::new();
+            loop {
+                match self.next()? {
+                    Some(Token::Word(pattern)) => patterns.push(Word::new(&pattern)),
+                    token => return Err(Self::unexpected(token)),
+                }
+                match self.next()? {
+                    Some(Token::Operator(Operator::Pipe)) => {}
+                    Some(Token::Operator(Operator::RightParen)) => break,
+                    token => return Err(Self::unexpected(token)),
+                }
+            }
+
+            let body = self.parse_list_until(|token| {
+                case_terminator(token).is_some() || is_keyword(token, &["esac"])
+            })?;
+            let terminator = match self.peek()?.and_then(case_terminator) {
+                Some(terminator) => {
+                    self.next()?;
+                    self.skip_newlines()?;
+                    terminator
+                }
+                // The last item may end directly with `esac`
+                None => CaseTerminator::Break,
+            };
+            items.push(CaseItem {
+                patterns,
+                body,
+                terminator,
+            });
+        }
+
+        Ok(CompoundCommand::Case { word, items })
+    }
+
+    fn parse_simple_command(&mut self) -> Result<SimpleCommand, ParseError> {
+        let mut command = SimpleCommand::default();
+
+        loop {
+            match self.peek()? {
+                Some(Token::Word(_)) => {
+                    if let Some(Token::Word(word)) = self.next()? {
+                        // Assignments are only recognized before the command name
+                        match split_assignment(&word) {
+                            Some(assignment) if command.words.is_empty() => {
+                                command.assignments.push(assignment)
+                            }
+                            _ => command.words.push(Word::new(&word)),
+                        }
+                    }
+                }
+                Some(Token::IoNumber(_)) | Some(Token::Redirect(_)) => {
+                    command.redirections.push(self.parse_redirection()?);
+                }
+                _ => break,
+            }
+        }
+
+        if command.assignments.is_empty()
+            && command.words.is_empty()
+            && command.redirections.is_empty()
+        {
+            return Err(Self::unexpected(self.next()?));
+        }
+
+        Ok(command)
+    }
+
+    fn parse_redirection(&mut self) -> Result<Redirection, ParseError> {
+        let fd = match self.peek()? {
+            Some(Token::IoNumber(fd)) => {
+                let fd = *fd;
+                self.next()?;
+                Some(fd)
+            }
+            _ => None,
+        };
+
+        let op = match self.next()? {
+            Some(Token::Redirect(op)) => op,
+            token => return Err(Self::unexpected(token)),
+        };
+
+        match self.next()? {
+            Some(Token::Word(target)) => {
+                let here_doc = match op {
+                    RedirectOp::HereDoc | RedirectOp::HereDocStrip => {
+                        let (delimiter, quoted) = here_doc_delimiter(&target);
+                        let here_doc = Rc::new(HereDoc {
+                            quoted,
+                            ..HereDoc::default()
+                        });
+                        let strip_tabs = op == RedirectOp::HereDocStrip;
+                        self.lexer
+                            .push_here_doc(delimiter, strip_tabs, Rc::clone(&here_doc));
+                        Some(here_doc)
+                    }
+                    _ => None,
+                };
+                Ok(Redirection {
+                    fd,
+                    op,
+                    target: Word::new(&target),
+                    here_doc,
+                })
+            }
+            Some(Token::Newline) | None => Err(ParseError::UnexpectedToken("newline".to_string())),
+            token => Err(Self::unexpected(token)),
+        }
+    }
+}
+
+/// Function names may contain most characters, but not quotes or expansions
+fn is_valid_function_name(name: &str) -> bool {
+    !name.is_empty()
+        && !RESERVED_WORDS.contains(&name)
+        && !name.contains(['\'', '"', '\\', '$', '`', '='])
+}
+
+/// The name of a simple command that consists of a single plain word
+fn function_name(command: &SimpleCommand) -> Option<String> {
+    match (
+        command.words.as_slice(),
+        command.assignments.is_empty(),
+        command.redirections.is_empty(),
+    ) {
+        ([word], true, true) if is_valid_function_name(&word.raw) => Some(word.raw.clone()),
+        _ => None,
+    }
+}
+
+fn is_keyword(token: &Token, keywords: &[&str]) -> bool {
+    matches!(token, Token::Word(word) if keywords.contains(&word.as_str()))
+}
+
+fn case_terminator(token: &Token) -> Option<CaseTerminator> {
+    match token {
+        Token::Operator(Operator::DoubleSemicolon) => Some(CaseTerminator::Break),
+        Token::Operator(Operator::SemicolonAnd) => Some(CaseTerminator::FallThrough),
+        Token::Operator(Operator::DoubleSemicolonAnd) => Some(CaseTerminator::Continue),
+        _ => None,
+    }
+}
+
+/// Remove quotes from a here-document delimiter, reporting whether any were present
+pub fn here_doc_delimiter(raw: &str) -> (String, bool) {
+    let mut delimiter = String::new();
+    let mut quoted = false;
+    let mut in_single_quotes = false;
+    let mut in_double_quotes = false;
+    let mut chars = raw.chars();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\'' if !in_double_quotes => {
+                quoted = true;
+                in_single_quotes = !in_single_quotes;
+            }
+            '"' if !in_single_quotes => {
+                quoted = true;
+                in_double_quotes = !in_double_quotes;
+            }
+            '\\' if !in_single_quotes => {
+                quoted = true;
+                if let Some(next_ch) = chars.next() {
+                    delimiter.push(next_ch);
+                }
+            }
+            _ => delimiter.push(ch),
+        }
+    }
+
+    (delimiter, quoted)
+}
+
+/// Split a `NAME=value` word into an assignment
+fn split_assignment(raw: &str) -> Option<Assignment> {
+    let (name, value) = raw.split_once('=')?;
+    if !is_valid_name(name) {
+        return None;
+    }
+    Some(Assignment {
+        name: name.to_string(),
+        value: Word::new(value),
+    })
+}
@@ -0,0 +1,253 @@
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, open, FcntlArg, OFlag};
+use nix::sys::stat::Mode;
+use nix::unistd::{close, dup2, lseek, mkstemp, unlink, write, Whence};
+use std::env;
+use std::io::{self, Write};
+use std::os::unix::io::RawFd;
+use thiserror::Error;
+
+use crate::ast::Redirection;
+use crate::expansion::{expand_here_doc, expand_word, ExpansionError};
+use crate::lexer::RedirectOp;
+use crate::shell::Shell;
+
+// Saved copies of redirected descriptors live above the range users normally touch
+const SAVED_FD_BASE: RawFd = 10;
+
+#[derive(Debug, Error)]
+pub enum RedirectError {
+    #[error("{0}: {}", .1.desc())]
+    Open(String, Errno),
+    #[error("{0}: Bad file descriptor")]
+    BadFd(String),
+    #[error("{0}: cannot overwrite existing file")]
+    Clobber(String),
+    #[error("{0}: redirection not supported")]
+    Unsupported(String),
+    #[error("cannot create temp file for here-document: {0}")]
+    HereDoc(&'static str),
+    #[error(transparent)]
+    Expansion(#[from] ExpansionError),
+}
+
+/// Descriptors that were replaced by redirections, restored when dropped
+#[derive(Default)]
+pub struct SavedFds {
+    /// Each redirected fd with a copy of its previous target, or `None` if it was closed
+    saved: Vec<(RawFd, Option<RawFd>)>,
+}
+
+impl SavedFds {
+    fn save(&mut self, fd: RawFd) {
+        if self.saved.iter().any(|(saved_fd, _)| *saved_fd == fd) {
+            return;
+        }
+        let copy = fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(SAVED_FD_BASE)).ok();
+        self.saved.push((fd, copy));
+    }
+}
+
+impl Drop for SavedFds {
+    fn drop(&mut self) {
+        let _ = io::stdout().flush();
+        let _ = io::stderr().flush();
+        for (fd, copy) in self.saved.drain(..).rev() {
+            match copy {
+                Some(copy) => {
+                    let _ = dup2(copy, fd);
+                    let _ = close(copy);
+                }
+                None => {
+                    let _ = close(fd);
+                }
+            }
+        }
+    }
+}
+
+/// Apply redirections to the current process for good, as a forked child does before exec
+pub fn apply_redirections(
+    shell: &mut Shell,
+    redirections: &[Redirection],
+) -> Result<(), RedirectError> {
+    for redirection in redirections {
+        apply_redirection(shell, redirection, None)?;
+    }
+    Ok(())
+}
+
+/// Apply redirections until the returned `SavedFds` is dropped, as builtins run in the shell do
+pub fn apply_redirections_saved(
+    shell: &mut Shell,
+    redirections: &[Redirection],
+) -> Result<SavedFds, RedirectError> {
+    let mut saved = SavedFds::default();
+    for redirection in redirections {
+        apply_redirection(shell, redirection, Some(&mut saved))?;
+    }
+    Ok(saved)
+}
+
+/// Make `fd` a copy of `source` until the returned `SavedFds` is dropped
+pub fn duplicate_saved(source: RawFd, fd: RawFd) -> Result<SavedFds, RedirectError> {
+    let mut saved = SavedFds::default();
+    saved.save(fd);
+    dup2(source, fd).map_err(|_| RedirectError::BadFd(source.to_string()))?;
+    Ok(saved)
+}
+
+fn default_fd(op: RedirectOp) -> RawFd {
+    match op {
+        RedirectOp::Input
+        | RedirectOp::ReadWrite
+        | RedirectOp::DupInput
+        | RedirectOp::HereDoc
+        | RedirectOp::HereDocStrip
+        | RedirectOp::HereString => 0,
+        _ => 1,
+    }
+}
+
+fn apply_redirection(
+    shell: &mut Shell,
+    redirection: &Redirection,
+    mut saved: Option<&mut SavedFds>,
+) -> Result<(), RedirectError> {
+    let fd = redirection.fd.unwrap_or_else(|| default_fd(redirection.op));
+    let target = expand_word(shell, &redirection.target)?;
+
+    let truncate = OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_TRUNC;
+    let append = OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_APPEND;
+
+    match redirection.op {
+        RedirectOp::Input => redirect_to_file(&target, OFlag::O_RDONLY, &[fd], saved),
+        RedirectOp::Output => {
+            check_clobber(shell, &target)?;
+            redirect_to_file(&target, truncate, &[fd], saved)
+        }
+        RedirectOp::Clobber => redirect_to_file(&target, truncate, &[fd], saved),
+        RedirectOp::Append => redirect_to_file(&target, append, &[fd], saved),
+        RedirectOp::ReadWrite => {
+            redirect_to_file(&target, OFlag::O_RDWR | OFlag::O_CREAT, &[fd], saved)
+        }
+        RedirectOp::OutputBoth => {
+            check_clobber(shell, &target)?;
+            redirect_to_file(&target, truncate, &[1, 2], saved)
+        }
+        RedirectOp::AppendBoth => redirect_to_file(&target, append, &[1, 2], saved),
+        RedirectOp::DupInput | RedirectOp::DupOutput => {
+            if let Some(saved) = saved.as_deref_mut() {
+                saved.save(fd);
+            }
+            if target == "-" {
+                let _ = close(fd);
+                return Ok(());
+            }
+            match target.parse::<RawFd>() {
+                Ok(source) => {
+                    if source != fd {
+                        dup2(source, fd).map_err(|_| RedirectError::BadFd(target))?;
+                    }
+                    Ok(())
+                }
+                // `>& file` without a descriptor number is the same as `&> file`
+                Err(_) if redirection.op == RedirectOp::DupOutput && redirection.fd.is_none() => {
+                    check_clobber(shell, &target)?;
+                    redirect_to_file(&target, truncate, &[1, 2], saved)
+                }
+                Err(_) => Err(RedirectError::BadFd(target)),
+            }
+        }
+        RedirectOp::HereDoc | RedirectOp::HereDocStrip => {
+            let here_doc = redirection
+                .here_doc
+                .as_ref()
+                .ok_or_else(|| RedirectError::Unsupported(redirection.op.as_str().to_string()))?;
+            let body = here_doc.body.borrow();
+            let body = if here_doc.quoted {
+                body.clone()
+            } else {
+                expand_here_doc(shell, &body)?
+            };
+            redirect_to_text(&body, fd, saved)
+        }
+        RedirectOp::HereString => redirect_to_text(&format!("{}\n", target), fd, saved),
+    }
+}
+
+/// Make `fd` read from `text` through an unlinked temporary file, which unlike
+/// a pipe cannot fill up and block before the command starts reading
+fn redirect_to_text(
+    text: &str,
+    fd: RawFd,
+    saved: Option<&mut SavedFds>,
+) -> Result<(), RedirectError> {
+    if let Some(saved) = saved {
+        saved.save(fd);
+    }
+
+    let template = env::temp_dir().join("shell-heredoc-XXXXXX");
+    let (file_fd, path) =
+        mkstemp(&template).map_err(|errno| RedirectError::HereDoc(errno.desc()))?;
+    let _ = unlink(&path);
+
+    let result = write_all(file_fd, text.as_bytes())
+        .and_then(|_| lseek(file_fd, 0, Whence::SeekSet).map(|_| ()))
+        .and_then(|_| {
+            if file_fd != fd {
+                dup2(file_fd, fd).map(|_| ())
+            } else {
+                Ok(())
+            }
+        });
+    if file_fd != fd {
+        let _ = close(file_fd);
+    }
+    result.map_err(|errno| RedirectError::HereDoc(errno.desc()))
+}
+
+fn write_all(fd: RawFd, mut bytes: &[u8]) -> nix::Result<()> {
+    while !bytes.is_empty() {
+        let written = write(fd, bytes)?;
+        bytes = &bytes[written..];
+    }
+    Ok(())
+}
+
+/// With `set -C`, `>` must not truncate an existing regular file; `>|` still may
+fn check_clobber(shell: &Shell, path: &str) -> Result<(), RedirectError> {
+    let exists = std::fs::metadata(path).is_ok_and(|metadata| metadata.is_file());
+    if shell.options.noclobber && exists {
+        return Err(RedirectError::Clobber(path.to_string()));
+    }
+    Ok(())
+}
+
+/// Open `path` and make every descriptor in `fds` refer to it
+fn redirect_to_file(
+    path: &str,
+    flags: OFlag,
+    fds: &[RawFd],
+    saved: Option<&mut SavedFds>,
+) -> Result<(), RedirectError> {
+    if let Some(saved) = saved {
+        for &fd in fds {
+            saved.save(fd);
+        }
+    }
+
+    let file_fd = open(path, flags, Mode::from_bits_truncate(0o666))
+        .map_err(|errno| RedirectError::Open(path.to_string(), errno))?;
+
+    for &fd in fds {
+        if fd != file_fd {
+            dup2(file_fd, fd).map_err(|_| RedirectError::BadFd(fd.to_string()))?;
+        }
+    }
+
+    if !fds.contains(&file_fd) {
+        let _ = close(file_fd);
+    }
+    Ok(())
+}
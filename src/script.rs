@@ -0,0 +1,191 @@
+use std::fs;
+use std::io;
+
+use nix::errno::Errno;
+use nix::unistd::{isatty, read};
+
+use crate::options::SetOptions;
+use crate::parser::parse;
+use crate::shell::Shell;
+
+/// How the shell was asked to run, from its command-line arguments
+#[derive(Debug, PartialEq, Eq)]
+pub enum Invocation {
+    /// Read commands from the terminal with a prompt
+    Interactive,
+    /// Read commands from stdin without a prompt
+    Stdin,
+    /// `-c string`: run the commands in the string
+    Command(String),
+    /// Run the commands in a script file
+    File(String),
+}
+
+/// Parse `[-ics] [-c string | file] [args...]` into how to run, setting `$0`
+/// and the positional parameters on `shell`
+pub fn parse_invocation(shell: &mut Shell, args: &[String]) -> Result<Invocation, String> {
+    let mut command = false;
+    let mut from_stdin = false;
+    let mut interactive = false;
+    if let Some(name) = args.first() {
+        shell.shell_name = name.clone();
+    }
+    let mut rest = args.get(1..).unwrap_or_default();
+    while let Some(arg) = rest.first() {
+        if arg == "--" {
+            rest = &rest[1..];
+            break;
+        }
+        let flags = match arg.strip_prefix('-') {
+            Some(flags) if !flags.is_empty() => flags,
+            _ => break,
+        };
+        // Options of `set` can be given as well, as in `-e` or `-o pipefail`
+        if flags == "o" {
+            let name = rest
+                .get(1)
+                .ok_or_else(|| "-o: option requires an argument".to_string())?;
+            if !shell.options.set(name, true) {
+                return Err(format!("{}: invalid option name", name));
+            }
+            rest = &rest[2..];
+            continue;
+        }
+        for flag in flags.chars() {
+            match flag {
+                'c' => command = true,
+                's' => from_stdin = true,
+                'i' => interactive = true,
+                _ => match SetOptions::letter_name(flag) {
+                    Some(name) => {
+                        shell.options.set(name, true);
+                    }
+                    None => return Err(format!("-{}: invalid option", flag)),
+                },
+            }
+        }
+        rest = &rest[1..];
+    }
+
+    let invocation = if command {
+        let (string, rest) = rest
+            .split_first()
+            .ok_or_else(|| "-c: option requires an argument".to_string())?;
+        if let Some((name, args)) = rest.split_first() {
+            shell.shell_name = name.clone();
+            shell.positional = args.to_vec();
+        }
+        shell.input_flag = Some('c');
+        Invocation::Command(string.clone())
+    } else if from_stdin || rest.is_empty() {
+        shell.positional = rest.to_vec();
+        shell.input_flag = Some('s');
+        if interactive || isatty(0).unwrap_or(false) {
+            Invocation::Interactive
+        } else {
+            Invocation::Stdin
+        }
+    } else {
+        shell.shell_name = rest[0].clone();
+        shell.positional = rest[1..].to_vec();
+        Invocation::File(rest[0].clone())
+    };
+    Ok(invocation)
+}
+
+/// Run the script at `path`
+pub fn run_file(shell: &mut Shell, path: &str) -> io::Result<()> {
+    let contents = fs::read(path)?;
+    let contents = String::from_utf8_lossy(&contents);
+    let mut lines = contents.split_inclusive('\n').map(str::to_string);
+    run_lines(shell, path, || lines.next());
+    Ok(())
+}
+
+/// Run the commands in a `-c` string
+pub fn run_string(shell: &mut Shell, commands: &str) {
+    let name = shell.shell_name.clone();
+    let mut lines = commands.split_inclusive('\n').map(str::to_string);
+    run_lines(shell, &name, || lines.next());
+}
+
+/// Run commands read from a stdin that is not a terminal
+pub fn run_stdin(shell: &mut Shell) {
+    let name = shell.shell_name.clone();
+    run_lines(shell, &name, read_stdin_line);
+}
+
+/// Read one line from stdin a byte at a time, leaving the rest of the
+/// input for the commands the script runs
+fn read_stdin_line() -> Option<String> {
+    let mut line = Vec::new();
+    let mut byte = [0u8];
+    loop {
+        match read(0, &mut byte) {
+            Ok(1) => {
+                line.push(byte[0]);
+                if byte[0] == b'\n' {
+                    break;
+                }
+            }
+            Err(Errno::EINTR) => continue,
+            _ => break,
+        }
+    }
+    if line.is_empty() {
+        None
+    } else {
+        Some(String::from_utf8_lossy(&line).into_owned())
+    }
+}
+
+/// Execute each complete command as soon as its last line has been read.
+/// A syntax error ends the script with status 2, as in a non-interactive bash.
+fn run_lines(shell: &mut Shell, name: &str, mut next_line: impl FnMut() -> Option<String>) {
+    let mut buffer = String::new();
+    let mut line_number = 0;
+    let mut start_line = 1;
+    loop {
+        let line = next_line();
+        let at_end = line.is_none();
+        if let Some(line) = line {
+            line_number += 1;
+            if shell.options.verbose {
+                eprintln!("{}", line.strip_suffix('\n').unwrap_or(&line));
+            }
+            buffer.push_str(&line);
+        }
+        if buffer.trim().is_empty() {
+            buffer.clear();
+            start_line = line_number + 1;
+            if at_end {
+                return;
+            }
+            continue;
+        }
+
+        match parse(&buffer) {
+            Ok(list) => {
+                // Blank lines and comments leave `$?` alone
+                if !list.items.is_empty() {
+                    shell.last_status = shell.execute_list(&list);
+                }
+                buffer.clear();
+                start_line = line_number + 1;
+            }
+            Err(e) if e.is_incomplete() && !at_end => {}
+            Err(e) => {
+                let line = if e.is_incomplete() {
+                    line_number
+                } else {
+                    start_line
+                };
+                eprintln!("{}: line {}: {}", name, line, e);
+                shell.exit_shell(2);
+            }
+        }
+        if at_end {
+            return;
+        }
+    }
+}
@@ -0,0 +1,659 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::mem;
+use std::process::exit;
+use std::rc::Rc;
+
+use nix::errno::Errno;
+use nix::unistd::{fork, getpid, ForkResult};
+use rustyline::history::{DefaultHistory, History};
+use rustyline::Editor;
+
+use crate::ast::{
+    AndOrList, AndOrOp, Command, CompoundCommand, List, Pipeline, Redirection, SimpleCommand, Word,
+};
+use crate::autocompletion::ShellCompleter;
+use crate::builtins;
+use crate::expansion::{expand_assignments, expand_prefix_assignments, expand_word, expand_words};
+use crate::format::{format_and_or, format_pipeline};
+use crate::hash::CommandHash;
+use crate::jobs::{Job, JobTable};
+use crate::options::{SetOptions, ShoptOptions};
+use crate::output::Output;
+use crate::parser::parse;
+use crate::redirection::{apply_redirections, apply_redirections_saved};
+use crate::signals;
+use crate::traps::{Trap, Traps};
+use crate::utils::*;
+use crate::variables::Variables;
+
+/// Commands the shell runs itself, in its own process or in a pipeline's
+pub const BUILT_IN_COMMANDS: [&str; 28] = [
+    "exit", "echo", "type", "pwd", "cd", "history", "export", "unset", "readonly", "set", "shopt",
+    "break", "continue", "local", "return", "declare", "shift", "jobs", "fg", "bg", "disown",
+    "trap", "read", "hash", "command", ":", "true", "false",
+];
+
+pub fn is_builtin(name: &str) -> bool {
+    BUILT_IN_COMMANDS.contains(&name)
+}
+
+// Function calls recurse on the Rust stack, so nesting is limited even without `FUNCNEST`
+const DEFAULT_FUNCTION_NESTING: usize = 1000;
+
+/// The deepest nesting `FUNCNEST` can allow, which the stack of the shell's thread has room for
+pub const MAX_FUNCTION_NESTING: usize = 10_000;
+
+/// A `break`, `continue` or `return` that is unwinding the commands around it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Jump {
+    Break(usize),
+    Continue(usize),
+    Return(i32),
+}
+
+/// State that lives for the whole shell session
+pub struct Shell {
+    pub editor: Editor<ShellCompleter, DefaultHistory>,
+    /// Exit status of the most recent foreground pipeline, exposed as `$?`
+    pub last_status: i32,
+    pub vars: Variables,
+    pub shopt: ShoptOptions,
+    /// Options toggled with `set -o` and `set +o`
+    pub options: SetOptions,
+    /// Exit status of the last command substitution while expanding the current command
+    pub substitution_status: Option<i32>,
+    /// Number of loops currently executing
+    pub loop_depth: usize,
+    pub pending_jump: Option<Jump>,
+    pub functions: HashMap<String, Rc<Command>>,
+    /// Number of function calls currently executing
+    pub function_depth: usize,
+    /// `$1`, `$2`, ... of the script or of the running function
+    pub positional: Vec<String>,
+    /// `$0`, the name of the shell or of the script it is running
+    pub shell_name: String,
+    /// Whether commands are read from a terminal with a prompt
+    pub interactive: bool,
+    /// `c` or `s` in `$-`, when commands come from `-c` or from stdin
+    pub input_flag: Option<char>,
+    /// `$$`, the process ID of the shell itself, unchanged in subshells
+    pub shell_pid: i32,
+    /// `$!`, the process ID of the most recent background command
+    pub last_background_pid: Option<i32>,
+    pub jobs: JobTable,
+    /// Whether each pipeline runs in its own process group, as `set -m`
+    pub job_control: bool,
+    /// Source text of the pipeline being executed, the name of a job if it stops
+    pub current_command: String,
+    pub traps: Traps,
+    /// Whether a trap action is running, during which no other trap runs
+    pub running_trap: bool,
+    /// Number of `if`, `while` and `until` conditions being executed
+    pub condition_depth: usize,
+    /// `PIPESTATUS`, the exit status of each command of the last foreground pipeline
+    pub pipe_status: Vec<i32>,
+    /// Assignments in front of the running builtin, which `command` passes on to a file it runs
+    pub builtin_assignments: Vec<(String, String)>,
+    pub hash: CommandHash,
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shell {
+    pub fn new() -> Self {
+        let mut editor = Editor::new().expect("Unable to initiate the prompt.");
+        editor.set_helper(Some(ShellCompleter::default()));
+        Shell {
+            editor,
+            last_status: 0,
+            vars: Variables::from_env(),
+            shopt: ShoptOptions::default(),
+            options: SetOptions::default(),
+            substitution_status: None,
+            loop_depth: 0,
+            pending_jump: None,
+            functions: HashMap::new(),
+            function_depth: 0,
+            positional: Vec::new(),
+            shell_name: String::from("codecrafters-shell"),
+            interactive: false,
+            input_flag: None,
+            shell_pid: getpid().as_raw(),
+            last_background_pid: None,
+            jobs: JobTable::default(),
+            job_control: false,
+            current_command: String::new(),
+            traps: Traps::default(),
+            running_trap: false,
+            condition_depth: 0,
+            pipe_status: Vec::new(),
+            builtin_assignments: Vec::new(),
+            hash: CommandHash::default(),
+        }
+    }
+
+    /// `$-`, the letters of the options that are currently on
+    pub fn option_flags(&self) -> String {
+        let mut flags: Vec<char> = SetOptions::LETTERS
+            .iter()
+            .filter(|(_, name)| self.options.get(name) == Some(true))
+            .map(|(letter, _)| *letter)
+            .collect();
+        if self.interactive {
+            flags.push('i');
+        }
+        if self.job_control {
+            flags.push('m');
+        }
+        // Lowercase letters first and where commands come from last, as bash lists them
+        flags.sort_by_key(|letter| (letter.is_ascii_uppercase(), *letter));
+        flags.extend(self.input_flag);
+        flags.into_iter().collect()
+    }
+
+    /// Parse and execute one line of input, recording its exit status
+    pub fn run(&mut self, input: &str) {
+        self.last_status = match parse(input) {
+            Ok(list) => self.execute_list(&list),
+            Err(e) => {
+                eprintln!("{}", e);
+                2
+            }
+        };
+    }
+
+    /// Execute every and-or list in order, returning the exit code of the last one
+    pub fn execute_list(&mut self, list: &List) -> i32 {
+        let mut exit_code = 0;
+        for and_or in &list.items {
+            // `set -n` stops a script from executing anything more, but not a terminal
+            if self.pending_jump.is_some() || (self.options.noexec && !self.interactive) {
+                break;
+            }
+            exit_code = if and_or.background {
+                self.execute_background(and_or)
+            } else {
+                self.execute_and_or(and_or)
+            };
+            self.last_status = exit_code;
+            // Reap background jobs as they finish so that no zombies accumulate
+            if signals::take_child_changed() {
+                self.jobs.reap();
+            }
+            self.run_pending_traps();
+        }
+        exit_code
+    }
+
+    /// Start an and-or list terminated by `&` as a job without waiting for it
+    fn execute_background(&mut self, and_or: &AndOrList) -> i32 {
+        if and_or.rest.is_empty() {
+            self.current_command = format_pipeline(&and_or.first);
+            return match execute_pipeline(self, &and_or.first.commands, true) {
+                Ok(_) => 0,
+                Err(e) => {
+                    eprintln!("Pipeline execution error: {}", e);
+                    1
+                }
+            };
+        }
+
+        // A list with `&&` or `||` runs in a subshell of its own
+        let command = format_and_or(and_or);
+        let _ = io::stdout().flush();
+        match unsafe { fork() } {
+            Ok(ForkResult::Child) => {
+                self.enter_child(None, false);
+                let exit_code = self.execute_and_or(and_or);
+                let _ = io::stdout().flush();
+                exit(exit_code);
+            }
+            Ok(ForkResult::Parent { child }) => {
+                self.set_child_process_group(child, child);
+                self.start_background(Job::new(child, &[child], &command));
+                0
+            }
+            Err(e) => {
+                eprintln!("fork: {}", e.desc());
+                1
+            }
+        }
+    }
+
+    /// Execute pipelines joined with `&&`/`||`, skipping those whose condition fails.
+    /// A failure that is not tested runs the `ERR` trap and, with `set -e`, exits.
+    fn execute_and_or(&mut self, and_or: &AndOrList) -> i32 {
+        let final_pipeline = and_or.rest.last().map_or(&and_or.first, |(_, p)| p);
+        let mut exit_code = self.execute_tested(&and_or.first, final_pipeline);
+        let mut last = &and_or.first;
+        for (op, pipeline) in &and_or.rest {
+            let should_run = match op {
+                AndOrOp::And => exit_code == 0,
+                AndOrOp::Or => exit_code != 0,
+            };
+            if should_run && self.pending_jump.is_none() {
+                self.last_status = exit_code;
+                exit_code = self.execute_tested(pipeline, final_pipeline);
+                last = pipeline;
+            }
+        }
+
+        // A failure tested by `&&`, `||`, `!`, `if` or `while` is not an error. Compound
+        // commands have already reported the failure of the command inside them,
+        // except subshells, whose traps are their own.
+        let tested =
+            self.condition_depth > 0 || last.negated || !std::ptr::eq(last, final_pipeline);
+        let is_compound = last.commands.len() == 1
+            && !matches!(
+                last.commands[0],
+                Command::Simple(_) | Command::Compound(CompoundCommand::Subshell(_), _)
+            );
+        if exit_code != 0 && !tested && !is_compound && self.pending_jump.is_none() {
+            self.last_status = exit_code;
+            self.run_trap(Trap::Err);
+            if self.options.errexit {
+                self.exit_shell(exit_code);
+            }
+        }
+        exit_code
+    }
+
+    /// Execute a pipeline of an and-or list. Everything run on the left of `&&`
+    /// or `||`, or under `!`, counts as a condition, even inside functions.
+    fn execute_tested(&mut self, pipeline: &Pipeline, final_pipeline: &Pipeline) -> i32 {
+        let is_condition = pipeline.negated || !std::ptr::eq(pipeline, final_pipeline);
+        if !is_condition {
+            return self.execute_pipeline_or_command(pipeline);
+        }
+        self.condition_depth += 1;
+        let exit_code = self.execute_pipeline_or_command(pipeline);
+        self.condition_depth -= 1;
+        exit_code
+    }
+
+    /// Execute a pipeline in the foreground, recording the status of each of its
+    /// commands in `PIPESTATUS`, and return the status of the whole pipeline
+    fn execute_pipeline_or_command(&mut self, pipeline: &Pipeline) -> i32 {
+        self.current_command = format_pipeline(pipeline);
+        let statuses = match pipeline.commands.as_slice() {
+            [command] => {
+                let exit_code = self.execute_command(command);
+                // Other compound commands leave the statuses of the pipelines inside them
+                if let Command::Compound(compound, _) = command {
+                    if !matches!(compound, CompoundCommand::Subshell(_)) {
+                        return self.negate(pipeline, exit_code);
+                    }
+                }
+                vec![exit_code]
+            }
+            commands => match execute_pipeline(self, commands, false) {
+                Ok(statuses) => statuses,
+                Err(e) => {
+                    eprintln!("Pipeline execution error: {}", e);
+                    vec![1]
+                }
+            },
+        };
+
+        let last = statuses.last().copied().unwrap_or(0);
+        let exit_code = if self.options.pipefail {
+            statuses
+                .iter()
+                .rev()
+                .find(|&&status| status != 0)
+                .copied()
+                .unwrap_or(0)
+        } else {
+            last
+        };
+        self.pipe_status = statuses;
+        self.negate(pipeline, exit_code)
+    }
+
+    /// The status of `pipeline`, inverted by a leading `!`
+    fn negate(&self, pipeline: &Pipeline, exit_code: i32) -> i32 {
+        if pipeline.negated {
+            (exit_code == 0) as i32
+        } else {
+            exit_code
+        }
+    }
+
+    /// Execute a single command in the shell process, returning its exit code
+    pub fn execute_command(&mut self, command: &Command) -> i32 {
+        match command {
+            Command::Simple(simple) => self.execute_simple_command(simple),
+            Command::Compound(compound, redirections) => {
+                let _saved_fds = match apply_redirections_saved(self, redirections) {
+                    Ok(saved_fds) => saved_fds,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        return 1;
+                    }
+                };
+                self.execute_compound(compound)
+            }
+            Command::FunctionDefinition { name, body } => {
+                self.functions.insert(name.clone(), Rc::clone(body));
+                0
+            }
+        }
+    }
+
+    /// Run a function with its own positional parameters and local scope
+    pub fn call_function(&mut self, name: &str, body: &Command, args: &[String]) -> i32 {
+        let max_depth = match self.vars.get("FUNCNEST").map(str::parse::<usize>) {
+            Some(Ok(max_depth)) if max_depth > 0 => max_depth.min(MAX_FUNCTION_NESTING),
+            _ => DEFAULT_FUNCTION_NESTING,
+        };
+        if self.function_depth >= max_depth {
+            eprintln!(
+                "{}: maximum function nesting level exceeded ({})",
+                name, max_depth
+            );
+            return 1;
+        }
+
+        let positional = std::mem::replace(&mut self.positional, args.to_vec());
+        // Loops around the call cannot be left with `break` from inside the function
+        let loop_depth = std::mem::replace(&mut self.loop_depth, 0);
+        let traps = self.traps.enter_function();
+        self.function_depth += 1;
+        self.vars.push_scope();
+
+        let mut exit_code = self.execute_command(body);
+        if let Some(Jump::Return(code)) = self.pending_jump {
+            exit_code = code;
+            self.pending_jump = None;
+        }
+        self.last_status = exit_code;
+        self.run_trap(Trap::Return);
+
+        self.traps.leave_function(traps);
+        self.vars.pop_scope();
+        self.function_depth -= 1;
+        self.loop_depth = loop_depth;
+        self.positional = positional;
+        exit_code
+    }
+
+    /// With `set -x`, show a command about to run on stderr, after the expanded `PS4`
+    pub fn trace_command(&mut self, assignments: &[(String, String)], words: &[String]) {
+        if !self.options.xtrace {
+            return;
+        }
+        let ps4 = self.vars.get("PS4").unwrap_or("+ ").to_string();
+        // Commands run while expanding `PS4` are not traced themselves
+        self.options.xtrace = false;
+        let prefix = expand_word(self, &Word::new(&ps4)).unwrap_or(ps4);
+        self.options.xtrace = true;
+
+        let words: Vec<String> = assignments
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, builtins::shell_quote(value)))
+            .chain(words.iter().map(|word| builtins::shell_quote(word)))
+            .collect();
+        eprintln!("{}{}", prefix, words.join(" "));
+    }
+
+    /// Execute a simple command that is not part of a pipeline, returning its exit code
+    fn execute_simple_command(&mut self, command: &SimpleCommand) -> i32 {
+        self.run_trap(Trap::Debug);
+        self.substitution_status = None;
+        // Words are expanded first; assignments without a command set shell
+        // variables, while those in front of one only last while it runs
+        let expanded = expand_words(self, &command.words).and_then(|inputs| {
+            let assignments = match inputs.is_empty() {
+                true => expand_assignments(self, &command.assignments)?,
+                false => expand_prefix_assignments(self, &command.assignments)?,
+            };
+            Ok((assignments, inputs))
+        });
+        let (assignments, inputs) = match expanded {
+            Ok(expanded) => expanded,
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        };
+        if !inputs.is_empty() {
+            self.trace_command(&assignments, &inputs);
+        }
+        let (command_name, args) = match inputs.split_first() {
+            Some((command_name, args)) => (command_name, args),
+            None => {
+                // Redirections without a command still create or truncate their files
+                return match apply_redirections_saved(self, &command.redirections) {
+                    // The status is that of the last command substitution, if any
+                    Ok(_) => self.substitution_status.unwrap_or(0),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        1
+                    }
+                };
+            }
+        };
+
+        self.run_command(
+            command_name,
+            args,
+            &assignments,
+            &command.redirections,
+            false,
+        )
+    }
+
+    /// Run an expanded command: a function, else a builtin, else a file in `PATH`.
+    /// A pipeline stage is `forked` already, so a file replaces its process.
+    pub fn run_command(
+        &mut self,
+        command_name: &str,
+        args: &[String],
+        assignments: &[(String, String)],
+        redirections: &[Redirection],
+        forked: bool,
+    ) -> i32 {
+        // Functions take precedence over builtins and commands in PATH
+        let function = self.functions.get(command_name).cloned();
+        if function.is_none() && !is_builtin(command_name) {
+            // Looked up before forking so that the hash table of the shell remembers it
+            let path = command_path(self, command_name, assignments);
+            if !forked {
+                return run_external_command(
+                    self,
+                    command_name,
+                    path,
+                    args,
+                    redirections,
+                    assignments,
+                );
+            }
+            if let Err(e) = apply_redirections(self, redirections) {
+                eprintln!("{}", e);
+                return 1;
+            }
+            exec_command(self, command_name, path, args, assignments);
+        }
+
+        let _saved_fds = match apply_redirections_saved(self, redirections) {
+            Ok(saved_fds) => saved_fds,
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        };
+
+        // Assignments in front of a function or builtin only last while it runs
+        let previous: Vec<_> = assignments
+            .iter()
+            .map(|(name, _)| (name.clone(), self.vars.get_variable(name).cloned()))
+            .collect();
+        let mut exit_code = 0;
+        for (name, value) in assignments {
+            if let Err(e) = self.vars.set(name, value) {
+                eprintln!("{}", e);
+                exit_code = 1;
+            }
+        }
+        if exit_code == 0 {
+            exit_code = match &function {
+                Some(body) => self.call_function(command_name, body, args),
+                None => {
+                    let outer = mem::replace(&mut self.builtin_assignments, assignments.to_vec());
+                    let exit_code = self.execute_builtin(command_name, args);
+                    self.builtin_assignments = outer;
+                    exit_code
+                }
+            };
+        }
+        for (name, var) in previous.into_iter().rev() {
+            self.vars.restore(&name, var);
+        }
+        exit_code
+    }
+
+    /// Execute a builtin in the current process, failing if its output cannot be written
+    pub fn execute_builtin(&mut self, command_name: &str, args: &[String]) -> i32 {
+        let mut out = Output::new();
+        let exit_code = self.dispatch_builtin(command_name, args, &mut out);
+        match out.finish() {
+            Ok(()) => exit_code,
+            Err(e) => {
+                let errno = Errno::from_i32(e.raw_os_error().unwrap_or(0));
+                eprintln!("{}: write error: {}", command_name, errno.desc());
+                1
+            }
+        }
+    }
+
+    fn dispatch_builtin(&mut self, command_name: &str, args: &[String], out: &mut Output) -> i32 {
+        match command_name {
+            "exit" => {
+                if let Some(history_on_startup) =
+                    self.vars.get("HISTFILE").filter(|_| self.interactive)
+                {
+                    let history_file = File::create(history_on_startup).unwrap();
+                    let mut history_writer = BufWriter::new(history_file);
+                    for entry in self.editor.history() {
+                        writeln!(history_writer, "{}", entry).unwrap();
+                    }
+                }
+                let exit_code = match args.first() {
+                    Some(code) => match code.parse::<i32>() {
+                        Ok(code) => code & 0xff,
+                        Err(_) => {
+                            eprintln!("exit: {}: numeric argument required", code);
+                            2
+                        }
+                    },
+                    None => self.last_status,
+                };
+                self.exit_shell(exit_code);
+            }
+            "history" => {
+                let history = self.editor.history();
+                if !args.is_empty() && !args[0].is_empty() {
+                    if args[0] == "-r" {
+                        let history_file_name = args[1].clone();
+                        let history_file = File::open(history_file_name).unwrap();
+                        let mut history_reader = BufReader::new(history_file);
+                        let mut line = String::new();
+                        while history_reader.read_line(&mut line).unwrap() > 0 {
+                            let _ = self.editor.add_history_entry(line.trim());
+                            line.clear();
+                        }
+                    } else if args[0] == "-w" {
+                        let history_file_name = args[1].clone();
+                        let history_file = File::create(history_file_name).unwrap();
+                        let mut history_writer = BufWriter::new(history_file);
+                        for entry in history.iter() {
+                            writeln!(history_writer, "{}", entry).unwrap();
+                        }
+                    } else if args[0] == "-a" {
+                        let history_file_name = args[1].clone();
+                        let history_file = OpenOptions::new()
+                            .append(true)
+                            .open(history_file_name)
+                            .unwrap();
+                        let mut history_writer = BufWriter::new(history_file);
+                        for entry in history.iter() {
+                            writeln!(history_writer, "{}", entry).unwrap();
+                        }
+                        let _ = self.editor.clear_history();
+                    } else {
+                        let start_index = history.len() - args[0].parse::<usize>().unwrap();
+                        for i in start_index..history.len() {
+                            writeln!(out, "    {}  {}", i + 1, history[i]);
+                        }
+                    }
+                } else {
+                    for (i, entry) in history.iter().enumerate() {
+                        writeln!(out, "    {}  {}", i + 1, entry);
+                    }
+                }
+                0
+            }
+            "pwd" => {
+                writeln!(out, "{}", env::current_dir().unwrap().to_string_lossy());
+                0
+            }
+            "cd" => {
+                let new_path = if args.is_empty() {
+                    match self.vars.get("HOME") {
+                        Some(home) => home.to_string(),
+                        None => {
+                            eprintln!("cd: HOME not set");
+                            return 1;
+                        }
+                    }
+                } else {
+                    args[0].clone()
+                };
+                let old_path = env::current_dir().ok();
+                if let Err(_e) = env::set_current_dir(&new_path) {
+                    eprintln!("cd: {}: No such file or directory", new_path);
+                    return 1;
+                }
+                if let Some(old_path) = old_path {
+                    let _ = self.vars.set("OLDPWD", &old_path.to_string_lossy());
+                }
+                if let Ok(current_dir) = env::current_dir() {
+                    let _ = self.vars.set("PWD", &current_dir.to_string_lossy());
+                }
+                0
+            }
+            "type" => builtins::command_type(self, args, out),
+            "command" => builtins::command(self, args, out),
+            ":" | "true" => 0,
+            "false" => 1,
+            "export" => builtins::export(self, args, out),
+            "unset" => builtins::unset(self, args),
+            "readonly" => builtins::readonly(self, args, out),
+            "set" => builtins::set(self, args, out),
+            "shopt" => builtins::shopt(self, args, out),
+            "break" | "continue" => builtins::loop_control(self, command_name, args),
+            "local" => builtins::local(self, args),
+            "return" => builtins::function_return(self, args),
+            "declare" => builtins::declare(self, args, out),
+            "shift" => builtins::shift(self, args),
+            "jobs" => builtins::jobs(self, args, out),
+            "fg" | "bg" => builtins::foreground_background(self, command_name, args, out),
+            "disown" => builtins::disown(self, args),
+            "trap" => builtins::trap(self, args, out),
+            "echo" => builtins::echo(args, out),
+            "read" => builtins::read(self, args),
+            "hash" => builtins::hash(self, args, out),
+            _ => {
+                eprintln!("{}: not a shell builtin", command_name);
+                1
+            }
+        }
+    }
+}
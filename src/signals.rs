@@ -0,0 +1,154 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use nix::sys::signal::{
+    raise, sigaction, signal as signal_disposition, SaFlags, SigAction, SigHandler, SigSet, Signal,
+};
+
+/// Signals an interactive shell ignores, so that only its foreground job receives them
+const INTERACTIVE_SIGNALS: [Signal; 5] = [
+    Signal::SIGINT,
+    Signal::SIGQUIT,
+    Signal::SIGTSTP,
+    Signal::SIGTTIN,
+    Signal::SIGTTOU,
+];
+
+/// Signals that end the shell unless trapped, caught while an `EXIT` trap is set so it runs first
+pub const FATAL_SIGNALS: [Signal; 3] = [Signal::SIGHUP, Signal::SIGINT, Signal::SIGTERM];
+
+/// Set by the `SIGCHLD` handler; the shell reaps its jobs when it sees it
+static CHILD_CHANGED: AtomicBool = AtomicBool::new(false);
+
+/// Signals caught for a `trap` whose action has not run yet, by signal number
+static PENDING: [AtomicBool; 32] = [const { AtomicBool::new(false) }; 32];
+
+extern "C" fn handle_sigchld(_: nix::libc::c_int) {
+    CHILD_CHANGED.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn handle_trapped(signal: nix::libc::c_int) {
+    if let Some(pending) = PENDING.get(signal as usize) {
+        pending.store(true, Ordering::SeqCst);
+    }
+    if signal == Signal::SIGCHLD as nix::libc::c_int {
+        CHILD_CHANGED.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Keep the interactive shell alive when the terminal sends job-control signals
+pub fn ignore_interactive_signals() {
+    for ignored in INTERACTIVE_SIGNALS {
+        let _ = unsafe { signal_disposition(ignored, SigHandler::SigIgn) };
+    }
+}
+
+/// Undo `ignore_interactive_signals` in a child, since ignored signals stay ignored across `exec`
+pub fn restore_default_signals() {
+    for ignored in INTERACTIVE_SIGNALS {
+        let _ = unsafe { signal_disposition(ignored, SigHandler::SigDfl) };
+    }
+}
+
+/// Commands started with `&` without job control cannot be interrupted from the terminal
+pub fn ignore_background_interrupts() {
+    for ignored in [Signal::SIGINT, Signal::SIGQUIT] {
+        let _ = unsafe { signal_disposition(ignored, SigHandler::SigIgn) };
+    }
+}
+
+/// Block every signal in the calling thread, so that they are delivered to the shell's thread
+pub fn block_in_thread() {
+    let _ = SigSet::all().thread_block();
+}
+
+/// Note every change of state of a child, restarting interrupted system calls
+pub fn install_child_handler() {
+    let action = SigAction::new(
+        SigHandler::Handler(handle_sigchld),
+        SaFlags::SA_RESTART | SaFlags::SA_NOCLDSTOP,
+        SigSet::empty(),
+    );
+    let _ = unsafe { sigaction(Signal::SIGCHLD, &action) };
+}
+
+/// Catch `signal` for a trap; its action runs later, between commands
+pub fn catch(signal: Signal) -> nix::Result<()> {
+    let action = SigAction::new(
+        SigHandler::Handler(handle_trapped),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    unsafe { sigaction(signal, &action) }.map(|_| ())
+}
+
+/// Catch `signal` so that the shell can run its `EXIT` trap before dying of it, unless it is ignored
+pub fn catch_fatal(signal: Signal) {
+    let action = SigAction::new(
+        SigHandler::Handler(handle_trapped),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    if let Ok(previous) = unsafe { sigaction(signal, &action) } {
+        if previous.handler() == SigHandler::SigIgn {
+            let _ = unsafe { sigaction(signal, &previous) };
+        }
+    }
+}
+
+/// Whether `signal` is currently ignored
+pub fn is_ignored(signal: Signal) -> bool {
+    let ignore = SigAction::new(SigHandler::SigIgn, SaFlags::empty(), SigSet::empty());
+    match unsafe { sigaction(signal, &ignore) } {
+        Ok(previous) => {
+            let _ = unsafe { sigaction(signal, &previous) };
+            previous.handler() == SigHandler::SigIgn
+        }
+        Err(_) => false,
+    }
+}
+
+/// Die of `signal` as if the shell had never caught it
+pub fn raise_default(signal: Signal) {
+    let _ = unsafe { signal_disposition(signal, SigHandler::SigDfl) };
+    let _ = raise(signal);
+}
+
+/// Give `signal` back the disposition it has without a trap
+pub fn reset(signal: Signal, interactive: bool) {
+    if signal == Signal::SIGCHLD {
+        install_child_handler();
+        return;
+    }
+    let handler = if interactive && INTERACTIVE_SIGNALS.contains(&signal) {
+        SigHandler::SigIgn
+    } else {
+        SigHandler::SigDfl
+    };
+    let _ = unsafe { signal_disposition(signal, handler) };
+}
+
+/// Ignore `signal` for a trap with an empty action
+pub fn ignore(signal: Signal) {
+    let _ = unsafe { signal_disposition(signal, SigHandler::SigIgn) };
+}
+
+/// Record `signal` as received, as when Ctrl-C is read at the prompt
+pub fn set_pending(signal: Signal) {
+    PENDING[signal as usize].store(true, Ordering::SeqCst);
+}
+
+/// Signals caught since the last call, in order of signal number
+pub fn take_pending() -> Vec<Signal> {
+    Signal::iterator()
+        .filter(|&signal| {
+            PENDING
+                .get(signal as usize)
+                .is_some_and(|pending| pending.swap(false, Ordering::SeqCst))
+        })
+        .collect()
+}
+
+/// Whether a child changed state since the last call
+pub fn take_child_changed() -> bool {
+    CHILD_CHANGED.swap(false, Ordering::SeqCst)
+}
@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::process;
+use std::str::FromStr;
+
+use nix::sys::signal::Signal;
+
+use crate::builtins::shell_quote;
+use crate::shell::Shell;
+use crate::signals;
+
+/// A condition `trap` can act on: a signal or one of the shell's pseudo-signals
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Trap {
+    Exit,
+    Signal(Signal),
+    Err,
+    Debug,
+    Return,
+}
+
+impl Trap {
+    /// Parse a condition given as a name with or without `SIG`, in any case, or as a number
+    pub fn parse(spec: &str) -> Option<Trap> {
+        if let Ok(number) = spec.parse::<i32>() {
+            return match number {
+                0 => Some(Trap::Exit),
+                number => Signal::try_from(number).ok().map(Trap::Signal),
+            };
+        }
+        let name = spec.to_ascii_uppercase();
+        match name.as_str() {
+            "EXIT" => Some(Trap::Exit),
+            "ERR" => Some(Trap::Err),
+            "DEBUG" => Some(Trap::Debug),
+            "RETURN" => Some(Trap::Return),
+            _ => {
+                let name = if name.starts_with("SIG") {
+                    name
+                } else {
+                    format!("SIG{}", name)
+                };
+                Signal::from_str(&name).ok().map(Trap::Signal)
+            }
+        }
+    }
+
+    /// The name `trap -p` shows
+    pub fn name(&self) -> &'static str {
+        match self {
+            Trap::Exit => "EXIT",
+            Trap::Signal(signal) => signal.as_str(),
+            Trap::Err => "ERR",
+            Trap::Debug => "DEBUG",
+            Trap::Return => "RETURN",
+        }
+    }
+}
+
+/// Trap actions that are set; an empty action means the condition is ignored
+#[derive(Debug, Clone, Default)]
+pub struct Traps {
+    actions: BTreeMap<Trap, String>,
+    /// The parent's actions, which `trap -p` still shows in a subshell until it sets a trap
+    inherited: Option<BTreeMap<Trap, String>>,
+}
+
+impl Traps {
+    pub fn get(&self, trap: Trap) -> Option<&str> {
+        self.actions.get(&trap).map(String::as_str)
+    }
+
+    /// Set the action for `trap`, catching or ignoring the signal it names
+    pub fn set(&mut self, trap: Trap, action: &str) -> nix::Result<()> {
+        match trap {
+            Trap::Signal(signal) if action.is_empty() => signals::ignore(signal),
+            Trap::Signal(signal) => signals::catch(signal)?,
+            Trap::Exit if !action.is_empty() => {
+                for signal in self.untrapped_fatal_signals() {
+                    signals::catch_fatal(signal);
+                }
+            }
+            _ => {}
+        }
+        self.actions.insert(trap, action.to_string());
+        self.inherited = None;
+        Ok(())
+    }
+
+    /// Remove the action for `trap`, restoring the signal's usual disposition
+    pub fn reset(&mut self, trap: Trap, interactive: bool) {
+        self.inherited = None;
+        if self.actions.remove(&trap).is_none() {
+            return;
+        }
+        match trap {
+            Trap::Signal(signal) => {
+                signals::reset(signal, interactive);
+                if self.catches_fatal(signal) {
+                    signals::catch_fatal(signal);
+                }
+            }
+            Trap::Exit => {
+                for signal in self.untrapped_fatal_signals() {
+                    if !signals::is_ignored(signal) {
+                        signals::reset(signal, interactive);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether `signal` is caught only so that it runs the `EXIT` trap before ending the shell
+    pub fn catches_fatal(&self, signal: Signal) -> bool {
+        signals::FATAL_SIGNALS.contains(&signal)
+            && !self.actions.contains_key(&Trap::Signal(signal))
+            && self
+                .get(Trap::Exit)
+                .is_some_and(|action| !action.is_empty())
+    }
+
+    fn untrapped_fatal_signals(&self) -> Vec<Signal> {
+        signals::FATAL_SIGNALS
+            .into_iter()
+            .filter(|&signal| !self.actions.contains_key(&Trap::Signal(signal)))
+            .collect()
+    }
+
+    /// A `trap` command that sets the action again, as `trap -p` prints it
+    pub fn format(&self, trap: Trap) -> Option<String> {
+        let action = self.listed().get(&trap)?;
+        Some(format!("trap -- {} {}", shell_quote(action), trap.name()))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Trap, &str)> {
+        self.listed()
+            .iter()
+            .map(|(&trap, action)| (trap, action.as_str()))
+    }
+
+    fn listed(&self) -> &BTreeMap<Trap, String> {
+        self.inherited.as_ref().unwrap_or(&self.actions)
+    }
+
+    /// A subshell forgets the actions of its parent, but ignored signals stay ignored
+    pub fn reset_for_subshell(&mut self) {
+        let inherited = self.actions.clone();
+        let traps: Vec<(Trap, bool)> = self
+            .actions
+            .iter()
+            .map(|(&trap, action)| (trap, action.is_empty()))
+            .collect();
+        for (trap, ignored) in traps {
+            match trap {
+                Trap::Signal(signal) if ignored => signals::ignore(signal),
+                _ if ignored => {}
+                _ => self.reset(trap, false),
+            }
+        }
+        self.inherited = Some(inherited);
+    }
+
+    /// Functions do not inherit the `ERR`, `DEBUG` and `RETURN` traps, which
+    /// are taken out while one runs and handed back by `leave_function`
+    pub fn enter_function(&mut self) -> Vec<(Trap, String)> {
+        [Trap::Err, Trap::Debug, Trap::Return]
+            .into_iter()
+            .filter_map(|trap| Some((trap, self.actions.remove(&trap)?)))
+            .collect()
+    }
+
+    /// Restore the traps saved by `enter_function` unless the function set its own
+    pub fn leave_function(&mut self, saved: Vec<(Trap, String)>) {
+        for (trap, action) in saved {
+            self.actions.entry(trap).or_insert(action);
+        }
+    }
+}
+
+impl Shell {
+    /// Run the action of `trap` if one is set, leaving `$?` as it was
+    pub fn run_trap(&mut self, trap: Trap) {
+        let action = match self.traps.get(trap) {
+            Some(action) if !action.is_empty() && !self.running_trap => action.to_string(),
+            _ => return,
+        };
+        let last_status = self.last_status;
+        self.running_trap = true;
+        self.run(&action);
+        self.running_trap = false;
+        self.last_status = last_status;
+    }
+
+    /// Run the actions of trapped signals that arrived since the last check
+    pub fn run_pending_traps(&mut self) {
+        for signal in signals::take_pending() {
+            // Ctrl-C at an interactive prompt is recorded too, but never ends the shell
+            if self.traps.catches_fatal(signal) && !signals::is_ignored(signal) {
+                self.die_of_signal(signal);
+            }
+            self.run_trap(Trap::Signal(signal));
+        }
+    }
+
+    /// Exit the shell with `code` after running the `EXIT` trap
+    pub fn exit_shell(&mut self, code: i32) -> ! {
+        self.run_exit_trap(code);
+        process::exit(code);
+    }
+
+    /// Run the `EXIT` trap for a signal that ends the shell, then die of the
+    /// signal so that the parent sees how the shell ended
+    fn die_of_signal(&mut self, signal: Signal) -> ! {
+        let code = 128 + signal as i32;
+        self.run_exit_trap(code);
+        signals::raise_default(signal);
+        process::exit(code);
+    }
+
+    fn run_exit_trap(&mut self, code: i32) {
+        self.last_status = code;
+        if let Some(action) = self.traps.get(Trap::Exit).map(str::to_string) {
+            // An `exit` inside the trap exits at once
+            self.traps.reset(Trap::Exit, self.interactive);
+            self.running_trap = false;
+            self.run(&action);
+        }
+        let _ = io::stdout().flush();
+    }
+}
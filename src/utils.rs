@@ -1,225 +1,190 @@
-use nix::sys::wait::waitpid;
-use nix::unistd::{close, dup2, fork, pipe, ForkResult};
-use std::io::{self, Write};
-use std::{env, fs::File, path::Path, process::Command};
-
-pub fn check_path(command: &str) -> Option<String> {
-    let key = "PATH";
-    let value = env::var(key).unwrap();
-    let paths = value.split(":").collect::<Vec<&str>>();
-    for path in paths {
-        let full_path = format!("{}/{}", path, command);
-        if Path::new(&full_path).exists() {
-            return Some(full_path);
-        }
+use nix::errno::Errno;
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{close, dup2, execve, fork, pipe, ForkResult};
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::FromRawFd;
+use std::path::Path;
+use std::process;
+
+use crate::ast::{Command, Redirection};
+use crate::expansion::{expand_prefix_assignments, expand_words};
+use crate::hash::find_in_path;
+use crate::jobs::Job;
+use crate::redirection::{apply_redirections, duplicate_saved};
+use crate::script;
+use crate::shell::Shell;
+
+/// Convert a status reported by `waitpid` into a shell exit code, using 128+n for signal n
+pub fn wait_status_code(status: WaitStatus) -> i32 {
+    match status {
+        WaitStatus::Exited(_, code) => code,
+        WaitStatus::Signaled(_, signal, _) | WaitStatus::Stopped(_, signal) => 128 + signal as i32,
+        _ => 1,
     }
-    None
 }
 
-pub fn parse_input(input: &str) -> Vec<String> {
-    let mut args = Vec::new();
-    let mut current_arg = String::new();
-    let mut in_single_quotes = false;
-    let mut in_double_quotes = false;
-    let mut chars = input.chars().peekable();
-
-    while let Some(ch) = chars.next() {
-        match ch {
-            '\\' if !in_single_quotes => {
-                if let Some(&next_ch) = chars.peek() {
-                    if in_double_quotes {
-                        match next_ch {
-                            '"' | '$' | '`' | '\\' | '\n' => {
-                                chars.next();
-                                current_arg.push(next_ch);
-                            }
-                            _ => current_arg.push('\\'),
-                        }
-                    } else {
-                        chars.next();
-                        current_arg.push(next_ch);
-                    }
-                } else {
-                    current_arg.push('\\');
-                }
-            }
-            '\'' if !in_double_quotes => in_single_quotes = !in_single_quotes,
-            '"' if !in_single_quotes => in_double_quotes = !in_double_quotes,
-            ch if ch.is_whitespace() && !in_single_quotes && !in_double_quotes => {
-                if !current_arg.is_empty() {
-                    args.push(current_arg.clone());
-                    current_arg.clear();
-                }
-            }
-            _ => current_arg.push(ch),
-        }
-    }
-
-    if !current_arg.is_empty() {
-        args.push(current_arg);
+/// Where `command_name` is found, using a `PATH` given in `assignments` if
+/// there is one, and otherwise the shell's, through its hash table
+pub fn command_path(
+    shell: &mut Shell,
+    command_name: &str,
+    assignments: &[(String, String)],
+) -> Option<String> {
+    let path_var = assignments
+        .iter()
+        .rev()
+        .find(|(name, _)| name == "PATH")
+        .map(|(_, value)| value.as_str());
+    match path_var {
+        Some(path_var) if !command_name.contains('/') => find_in_path(command_name, Some(path_var)),
+        _ => shell.find_command(command_name),
     }
-    args
 }
 
-/// Check if the input contains a pipeline (| operator)
-pub fn has_pipeline(input: &str) -> bool {
-    let mut in_single_quotes = false;
-    let mut in_double_quotes = false;
-    let mut chars = input.chars().peekable();
-
-    while let Some(ch) = chars.next() {
-        match ch {
-            '\\' if !in_single_quotes => {
-                // Skip the next character if it's escaped
-                chars.next();
-            }
-            '\'' if !in_double_quotes => in_single_quotes = !in_single_quotes,
-            '"' if !in_single_quotes => in_double_quotes = !in_double_quotes,
-            '|' if !in_single_quotes && !in_double_quotes => {
-                return true;
-            }
-            _ => {}
+/// Replace the current (forked) process with an external command found at
+/// `path`, whose environment is the shell's exported variables plus `assignments`
+pub fn exec_command(
+    shell: &mut Shell,
+    command_name: &str,
+    path: Option<String>,
+    args: &[String],
+    assignments: &[(String, String)],
+) -> ! {
+    let path = match path {
+        Some(path) => path,
+        None => {
+            eprintln!("{}: command not found", command_name);
+            process::exit(127);
         }
-    }
-    false
-}
-
-/// Split input into separate commands based on pipeline operators
-pub fn split_pipeline(input: &str) -> Vec<String> {
-    let mut commands = Vec::new();
-    let mut current_command = String::new();
-    let mut in_single_quotes = false;
-    let mut in_double_quotes = false;
-    let mut chars = input.chars().peekable();
-
-    while let Some(ch) = chars.next() {
-        match ch {
-            '\\' if !in_single_quotes => {
-                // Skip the next character if it's escaped
-                if let Some(next_ch) = chars.next() {
-                    current_command.push(ch);
-                    current_command.push(next_ch);
-                } else {
-                    current_command.push(ch);
-                }
-            }
-            '\'' if !in_double_quotes => in_single_quotes = !in_single_quotes,
-            '"' if !in_single_quotes => in_double_quotes = !in_double_quotes,
-            '|' if !in_single_quotes && !in_double_quotes => {
-                // Found a pipeline operator, save current command and start new one
-                commands.push(current_command.trim().to_string());
-                current_command.clear();
+    };
+
+    let to_cstring = |arg: &str| CString::new(arg).unwrap_or_default();
+    let argv: Vec<CString> = std::iter::once(command_name)
+        .chain(args.iter().map(String::as_str))
+        .map(to_cstring)
+        .collect();
+
+    let _ = io::stdout().flush();
+    let env = shell.vars.environment(assignments);
+    let errno = match execve(&to_cstring(&path), &argv, &env) {
+        Err(errno) => errno,
+        Ok(never) => match never {},
+    };
+    if errno == Errno::ENOEXEC {
+        // A file without a `#!` line is a script for this shell, run in the forked copy
+        shell.functions.clear();
+        for (name, value) in assignments {
+            let _ = shell.vars.export(name, Some(value));
+        }
+        shell.shell_name = path.clone();
+        shell.positional = args.to_vec();
+        match script::run_file(shell, &path) {
+            Ok(()) => process::exit(shell.last_status),
+            Err(e) => {
+                eprintln!("{}: {}", command_name, e);
+                process::exit(126);
             }
-            _ => current_command.push(ch),
         }
     }
-
-    // Add the last command
-    if !current_command.trim().is_empty() {
-        commands.push(current_command.trim().to_string());
+    if errno == Errno::EACCES && Path::new(&path).is_dir() {
+        eprintln!("{}: Is a directory", command_name);
+        process::exit(126);
     }
-
-    commands
+    eprintln!("{}: {}", command_name, errno.desc());
+    process::exit(if errno == Errno::ENOENT { 127 } else { 126 });
 }
 
-pub fn check_for_stdout_redirect(args: &[String]) -> usize {
-    for (i, arg) in args.iter().enumerate() {
-        if arg == ">" || arg == "1>" {
-            return i;
+/// Fork and run an external command in the foreground with its redirections applied
+pub fn run_external_command(
+    shell: &mut Shell,
+    command_name: &str,
+    path: Option<String>,
+    args: &[String],
+    redirections: &[Redirection],
+    assignments: &[(String, String)],
+) -> i32 {
+    let _ = io::stdout().flush();
+    match unsafe { fork() } {
+        Ok(ForkResult::Child) => {
+            shell.enter_child(None, true);
+            if let Err(e) = apply_redirections(shell, redirections) {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+            exec_command(shell, command_name, path, args, assignments);
         }
-    }
-    args.len() - 1
-}
-
-pub fn check_for_append_stdout(args: &[String]) -> usize {
-    for (i, arg) in args.iter().enumerate() {
-        if arg == ">>" || arg == "1>>" {
-            return i;
+        Ok(ForkResult::Parent { child }) => {
+            shell.set_child_process_group(child, child);
+            let job = Job::new(child, &[child], &shell.current_command);
+            shell.wait_for_foreground(job)
         }
-    }
-    args.len() - 1
-}
-
-pub fn check_for_stderr_redirect(args: &[String]) -> usize {
-    for (i, arg) in args.iter().enumerate() {
-        if arg == "2>" {
-            return i;
+        Err(e) => {
+            eprintln!("fork: {}", e.desc());
+            1
         }
     }
-    args.len() - 1
 }
 
-pub fn check_for_append_stderr(args: &[String]) -> usize {
-    for (i, arg) in args.iter().enumerate() {
-        if arg == "2>>" {
-            return i;
+/// Run `command` in a forked subshell and return what it wrote to stdout
+/// with trailing newlines removed, recording its exit status
+pub fn command_substitution(shell: &mut Shell, command: &str) -> nix::Result<String> {
+    let _ = io::stdout().flush();
+    let (read_fd, write_fd) = pipe()?;
+    match unsafe { fork() } {
+        Ok(ForkResult::Child) => {
+            shell.enter_subshell();
+            let _ = close(read_fd);
+            if dup2(write_fd, 1).is_err() {
+                process::exit(1);
+            }
+            let _ = close(write_fd);
+            shell.run(command);
+            let _ = io::stdout().flush();
+            process::exit(shell.last_status);
+        }
+        Ok(ForkResult::Parent { child }) => {
+            let _ = close(write_fd);
+            let mut output = Vec::new();
+            let mut reader = unsafe { File::from_raw_fd(read_fd) };
+            let _ = reader.read_to_end(&mut output);
+            let status = waitpid(child, None).map(wait_status_code).unwrap_or(1);
+            shell.last_status = status;
+            shell.substitution_status = Some(status);
+
+            let mut output = String::from_utf8_lossy(&output).into_owned();
+            output.truncate(output.trim_end_matches('\n').len());
+            Ok(output)
+        }
+        Err(e) => {
+            let _ = close(read_fd);
+            let _ = close(write_fd);
+            Err(e)
         }
-    }
-    args.len() - 1
-}
-
-pub fn run_command(command_name: &str, args: &[String]) {
-    let output = Command::new(command_name).args(args).output().unwrap();
-    io::stdout().write_all(&output.stdout).unwrap();
-    io::stderr().write_all(&output.stderr).unwrap();
-}
-
-pub fn run_command_with_stdout_redirect(command_name: &str, args: &[String], file_name: &str) {
-    let output = Command::new(command_name).args(args).output().unwrap();
-    let mut file = File::create(file_name).unwrap();
-    file.write_all(&output.stdout).unwrap();
-    io::stderr().write_all(&output.stderr).unwrap();
-}
-
-pub fn run_command_with_stderr_redirect(command_name: &str, args: &[String], file_name: &str) {
-    let output = Command::new(command_name).args(args).output().unwrap();
-    let mut file = File::create(file_name).unwrap();
-    io::stdout().write_all(&output.stdout).unwrap();
-    file.write_all(&output.stderr).unwrap();
-}
-
-pub fn run_command_with_append_stdout_redirect(
-    command_name: &str,
-    args: &[String],
-    file_name: &str,
-) {
-    let output = Command::new(command_name).args(args).output().unwrap();
-    if Path::new(file_name).exists() {
-        let mut file = File::options().append(true).open(file_name).unwrap();
-        file.write_all(&output.stdout).unwrap();
-        io::stderr().write_all(&output.stderr).unwrap();
-    } else {
-        let mut file = File::create(file_name).unwrap();
-        file.write_all(&output.stdout).unwrap();
-        io::stderr().write_all(&output.stderr).unwrap();
-    }
-}
-
-pub fn run_command_with_append_stderr_redirect(
-    command_name: &str,
-    args: &[String],
-    file_name: &str,
-) {
-    let output = Command::new(command_name).args(args).output().unwrap();
-    if Path::new(file_name).exists() {
-        let mut file = File::options().append(true).open(file_name).unwrap();
-        file.write_all(&output.stderr).unwrap();
-        io::stdout().write_all(&output.stdout).unwrap();
-    } else {
-        let mut file = File::create(file_name).unwrap();
-        file.write_all(&output.stderr).unwrap();
-        io::stdout().write_all(&output.stdout).unwrap();
     }
 }
 
-/// Execute a pipeline of commands using pipes and process management
-pub fn execute_pipeline(commands: &[String]) -> Result<(), Box<dyn std::error::Error>> {
-    if commands.len() < 2 {
-        return Err("Pipeline must have at least 2 commands".into());
-    }
-
+/// Execute a pipeline of commands using pipes and process management as one
+/// job, returning the exit status of each command, or nothing at once in the background.
+/// With `shopt -s lastpipe` and no job control, the last command runs in the shell
+/// itself, reading from the pipe, so that what it changes outlives the pipeline.
+pub fn execute_pipeline(
+    shell: &mut Shell,
+    commands: &[Command],
+    background: bool,
+) -> Result<Vec<i32>, Box<dyn std::error::Error>> {
+    let _ = io::stdout().flush();
     let mut child_pids = Vec::new();
+    // The first process leads the process group of the whole pipeline
+    let mut pgid = None;
     let mut prev_pipe_read = None;
+    let lastpipe = shell.shopt.lastpipe && !shell.job_control && !background;
+    let forked = if lastpipe {
+        commands.len() - 1
+    } else {
+        commands.len()
+    };
 
     // Helper function to safely close file descriptors
     fn safe_close(fd: i32) {
@@ -228,16 +193,7 @@ pub fn execute_pipeline(commands: &[String]) -> Result<(), Box<dyn std::error::E
         }
     }
 
-    // Execute all commands including the last one in child processes
-    for (i, command_str) in commands.iter().enumerate() {
-        let args = parse_input(command_str);
-        if args.is_empty() {
-            continue;
-        }
-
-        let command_name = &args[0];
-        let command_args = &args[1..];
-
+    for (i, command) in commands[..forked].iter().enumerate() {
         let pipe_info = if i < commands.len() - 1 {
             // Not the last command, create a pipe
             Some(pipe()?)
@@ -251,6 +207,8 @@ pub fn execute_pipeline(commands: &[String]) -> Result<(), Box<dyn std::error::E
             ForkResult::Parent { child } => {
                 // Parent process
                 child_pids.push(child);
+                let leader = *pgid.get_or_insert(child);
+                shell.set_child_process_group(child, leader);
 
                 // Close the write end of the pipe in parent (if any)
                 if let Some((pipe_read, pipe_write)) = pipe_info {
@@ -271,7 +229,8 @@ pub fn execute_pipeline(commands: &[String]) -> Result<(), Box<dyn std::error::E
             }
             ForkResult::Child => {
                 // Child process - ensure proper cleanup on exit
-                let result = (|| -> Result<(), Box<dyn std::error::Error>> {
+                shell.enter_child(pgid, !background);
+                let result = (|| -> Result<i32, Box<dyn std::error::Error>> {
                     // Redirect stdin from previous pipe if it exists
                     if let Some(prev_read) = prev_pipe_read {
                         dup2(prev_read, 0)?; // Redirect stdin
@@ -285,126 +244,72 @@ pub fn execute_pipeline(commands: &[String]) -> Result<(), Box<dyn std::error::E
                         safe_close(pipe_write);
                     }
 
-                    // Execute the command
-                    execute_single_command(command_name, command_args)?;
-                    Ok(())
+                    let command = match command {
+                        Command::Simple(command) => command,
+                        // Compound commands run in this forked copy of the shell
+                        compound => return Ok(shell.execute_command(compound)),
+                    };
+
+                    // Each stage expands its words in its own process, like a subshell
+                    let args = expand_words(shell, &command.words)?;
+                    let assignments = expand_prefix_assignments(shell, &command.assignments)?;
+                    shell.trace_command(&assignments, &args);
+
+                    let (command_name, command_args) = match args.split_first() {
+                        Some(split) => split,
+                        None => {
+                            apply_redirections(shell, &command.redirections)?;
+                            return Ok(0);
+                        }
+                    };
+                    Ok(shell.run_command(
+                        command_name,
+                        command_args,
+                        &assignments,
+                        &command.redirections,
+                        true,
+                    ))
                 })();
 
                 // Exit with appropriate code
-                match result {
-                    Ok(_) => std::process::exit(0),
-                    Err(_) => std::process::exit(1),
-                }
+                let exit_code = result.unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    1
+                });
+                let _ = io::stdout().flush();
+                process::exit(exit_code);
             }
         }
     }
 
-    // Wait for all child processes to complete and handle any errors
-    for child_pid in child_pids {
-        match waitpid(child_pid, None) {
-            Ok(_) => {}
-            Err(e) => {
-                // Log error but don't fail the entire pipeline
-                eprintln!(
-                    "Warning: Failed to wait for child process {}: {}",
-                    child_pid, e
-                );
-            }
+    let last_status = match prev_pipe_read.filter(|_| lastpipe) {
+        Some(pipe_read) => {
+            let exit_code = match duplicate_saved(pipe_read, 0) {
+                Ok(_saved_fds) => shell.execute_command(&commands[forked]),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    1
+                }
+            };
+            safe_close(pipe_read);
+            Some(exit_code)
         }
+        None => None,
+    };
+    if child_pids.is_empty() {
+        return Ok(last_status.into_iter().collect());
     }
 
-    Ok(())
-}
-
-/// Execute a single command (built-in or external)
-fn execute_single_command(
-    command_name: &str,
-    args: &[String],
-) -> Result<(), Box<dyn std::error::Error>> {
-    match command_name {
-        "echo" => {
-            let output = args.join(" ");
-            println!("{}", output);
-            Ok(())
-        }
-        "exit" => {
-            // In a pipeline, exit should terminate the current process
-            std::process::exit(0);
-        }
-        "pwd" => {
-            let current_dir = env::current_dir()?;
-            println!("{}", current_dir.to_string_lossy());
-            Ok(())
-        }
-        "cd" => {
-            // Note: cd in a pipeline doesn't affect the parent shell's directory
-            // This is expected behavior for most shells
-            let new_path = if args.is_empty() || args[0] == "~" {
-                env::var("HOME")?
-            } else {
-                args[0].clone()
-            };
-            env::set_current_dir(&new_path)?;
-            Ok(())
-        }
-        "type" => {
-            let builtins = ["exit", "echo", "type", "pwd", "cd", "ls"];
-            if builtins.contains(&args[0].as_str()) {
-                println!("{} is a shell builtin", args[0]);
-            } else {
-                let path = check_path(&args[0]);
-                if let Some(path) = path {
-                    println!("{} is {}", args[0], path);
-                } else {
-                    eprintln!("{}: not found", args[0]);
-                }
-            }
-            Ok(())
-        }
-        "ls" => {
-            let mut cmd = Command::new("ls");
-            cmd.args(args);
-            let status = cmd.status()?;
-            if !status.success() {
-                return Err(format!("ls command failed with status: {}", status).into());
-            }
-            Ok(())
-        }
-        "cat" => {
-            let mut cmd = Command::new("cat");
-            cmd.args(args);
-            let status = cmd.status()?;
-            if !status.success() {
-                return Err(format!("cat command failed with status: {}", status).into());
-            }
-            Ok(())
-        }
-        _ => {
-            // External command - handle special cases for pipeline commands
-            let path = check_path(command_name);
-            match path {
-                Some(_path) => {
-                    // Special handling for commands that might need different behavior in pipelines
-                    {
-                        // Generic external command handling
-                        let mut cmd = Command::new(command_name);
-                        cmd.args(args);
-                        let status = cmd.status()?;
-                        if !status.success() {
-                            return Err(format!(
-                                "{} command failed with status: {}",
-                                command_name, status
-                            )
-                            .into());
-                        }
-                        Ok(())
-                    }
-                }
-                None => {
-                    eprintln!("{}: command not found", command_name);
-                    Err(format!("{}: command not found", command_name).into())
-                }
-            }
-        }
+    let job = Job::new(
+        pgid.unwrap_or(child_pids[0]),
+        &child_pids,
+        &shell.current_command,
+    );
+    if background {
+        shell.start_background(job);
+        return Ok(Vec::new());
     }
+    let mut statuses = shell.wait_for_pipeline(job);
+    statuses.extend(last_status);
+    Ok(statuses)
 }
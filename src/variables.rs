@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::env;
+use std::ffi::CString;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum VariableError {
+    #[error("{0}: readonly variable")]
+    ReadOnly(String),
+    #[error("`{0}': not a valid identifier")]
+    InvalidName(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Variable {
+    pub value: String,
+    /// Exported variables are passed to the environment of child processes
+    pub exported: bool,
+    pub readonly: bool,
+}
+
+/// The shell's variable table, holding both shell-local and exported variables
+#[derive(Debug, Clone, Default)]
+pub struct Variables {
+    vars: HashMap<String, Variable>,
+    /// One frame per running function, holding the values its `local`
+    /// variables hid, which come back when the function returns
+    scopes: Vec<HashMap<String, Option<Variable>>>,
+}
+
+/// Whether `name` can be used as a variable name: a letter or underscore
+/// followed by letters, digits and underscores
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(ch) if ch.is_ascii_alphabetic() || ch == '_' => {}
+        _ => return false,
+    }
+    chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+}
+
+impl Variables {
+    /// Start with every variable of the inherited environment, marked as exported
+    pub fn from_env() -> Self {
+        let vars = env::vars_os()
+            .filter_map(|(name, value)| {
+                let name = name.into_string().ok()?;
+                let value = value.into_string().ok()?;
+                Some((
+                    name,
+                    Variable {
+                        value,
+                        exported: true,
+                        readonly: false,
+                    },
+                ))
+            })
+            .collect();
+        Variables {
+            vars,
+            scopes: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(|var| var.value.as_str())
+    }
+
+    pub fn get_variable(&self, name: &str) -> Option<&Variable> {
+        self.vars.get(name)
+    }
+
+    /// Assign a value, keeping the variable's exported and readonly attributes
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), VariableError> {
+        if !is_valid_name(name) {
+            return Err(VariableError::InvalidName(name.to_string()));
+        }
+        let var = self.vars.entry(name.to_string()).or_default();
+        if var.readonly {
+            return Err(VariableError::ReadOnly(name.to_string()));
+        }
+        var.value = value.to_string();
+        Ok(())
+    }
+
+    /// Mark a variable as exported, optionally assigning it first
+    pub fn export(&mut self, name: &str, value: Option<&str>) -> Result<(), VariableError> {
+        match value {
+            Some(value) => self.set(name, value)?,
+            None if !is_valid_name(name) => {
+                return Err(VariableError::InvalidName(name.to_string()))
+            }
+            None => {}
+        }
+        self.vars.entry(name.to_string()).or_default().exported = true;
+        Ok(())
+    }
+
+    /// Remove the export attribute but keep the variable in the shell
+    pub fn unexport(&mut self, name: &str) {
+        if let Some(var) = self.vars.get_mut(name) {
+            var.exported = false;
+        }
+    }
+
+    /// Mark a variable as readonly, optionally assigning it first
+    pub fn set_readonly(&mut self, name: &str, value: Option<&str>) -> Result<(), VariableError> {
+        match value {
+            Some(value) => self.set(name, value)?,
+            None if !is_valid_name(name) => {
+                return Err(VariableError::InvalidName(name.to_string()))
+            }
+            None => {}
+        }
+        self.vars.entry(name.to_string()).or_default().readonly = true;
+        Ok(())
+    }
+
+    pub fn unset(&mut self, name: &str) -> Result<(), VariableError> {
+        if !is_valid_name(name) {
+            return Err(VariableError::InvalidName(name.to_string()));
+        }
+        if self.vars.get(name).is_some_and(|var| var.readonly) {
+            return Err(VariableError::ReadOnly(name.to_string()));
+        }
+        self.vars.remove(name);
+        Ok(())
+    }
+
+    /// Put back a variable exactly as it was, used to undo temporary assignments
+    pub fn restore(&mut self, name: &str, previous: Option<Variable>) {
+        match previous {
+            Some(var) => {
+                self.vars.insert(name.to_string(), var);
+            }
+            None => {
+                self.vars.remove(name);
+            }
+        }
+    }
+
+    /// Start the local scope of a function call
+    pub fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    /// End a function call, bringing back the variables its locals hid
+    pub fn pop_scope(&mut self) {
+        if let Some(scope) = self.scopes.pop() {
+            for (name, previous) in scope {
+                self.restore(&name, previous);
+            }
+        }
+    }
+
+    pub fn in_function(&self) -> bool {
+        !self.scopes.is_empty()
+    }
+
+    /// Make `name` local to the current function. Without a value it starts
+    /// unset; other functions called from here see the local (dynamic scoping).
+    pub fn make_local(&mut self, name: &str, value: Option<&str>) -> Result<(), VariableError> {
+        if !is_valid_name(name) {
+            return Err(VariableError::InvalidName(name.to_string()));
+        }
+        if self.vars.get(name).is_some_and(|var| var.readonly) {
+            return Err(VariableError::ReadOnly(name.to_string()));
+        }
+        let Some(scope) = self.scopes.last_mut() else {
+            return Ok(());
+        };
+        if !scope.contains_key(name) {
+            scope.insert(name.to_string(), self.vars.remove(name));
+        }
+        match value {
+            Some(value) => self.set(name, value),
+            None => Ok(()),
+        }
+    }
+
+    /// All variables sorted by name
+    pub fn iter(&self) -> Vec<(&String, &Variable)> {
+        let mut vars: Vec<_> = self.vars.iter().collect();
+        vars.sort_by(|a, b| a.0.cmp(b.0));
+        vars
+    }
+
+    /// The environment for a child process: every exported variable plus the
+    /// assignments written in front of the command
+    pub fn environment(&self, assignments: &[(String, String)]) -> Vec<CString> {
+        let mut env: HashMap<&str, &str> = self
+            .vars
+            .iter()
+            .filter(|(_, var)| var.exported)
+            .map(|(name, var)| (name.as_str(), var.value.as_str()))
+            .collect();
+        for (name, value) in assignments {
+            env.insert(name, value);
+        }
+
+        let mut env: Vec<_> = env.into_iter().collect();
+        env.sort();
+        env.into_iter()
+            .filter_map(|(name, value)| CString::new(format!("{}={}", name, value)).ok())
+            .collect()
+    }
+}